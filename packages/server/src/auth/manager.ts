@@ -7,6 +7,8 @@ import { SessionManager } from "./session-manager"
 import { isLoopbackAddress, parseCookies } from "./http-auth"
 
 export const BOOTSTRAP_TOKEN_STDOUT_PREFIX = "AGROFORGE_BOOTSTRAP_TOKEN:" as const
+/** Desktop hosts hand over a pre-generated bootstrap token here instead of reading it from stdout. */
+export const BOOTSTRAP_TOKEN_ENV = "AGROFORGE_BOOTSTRAP_TOKEN" as const
 export const DEFAULT_AUTH_USERNAME = "codenomad" as const
 export const DEFAULT_AUTH_COOKIE_NAME = "codenomad_session" as const
 
@@ -45,9 +47,9 @@ export class AuthManager {
     return Boolean(this.tokenManager)
   }
 
-  issueBootstrapToken(): string | null {
+  issueBootstrapToken(preset?: string): string | null {
     if (!this.tokenManager) return null
-    return this.tokenManager.generate()
+    return this.tokenManager.generate(preset)
   }
 
   consumeBootstrapToken(token: string): boolean {
@@ -111,3 +113,14 @@ function buildSessionCookie(name: string, value: string, options?: { maxAgeSecon
   }
   return parts.join("; ")
 }
+
+/**
+ * Reads the host-provided bootstrap token and removes it from the environment
+ * so that spawned OpenCode instances and background processes never inherit it.
+ */
+export function takeBootstrapTokenFromEnv(env: NodeJS.ProcessEnv = process.env): string | undefined {
+  const raw = env[BOOTSTRAP_TOKEN_ENV]
+  delete env[BOOTSTRAP_TOKEN_ENV]
+  const token = raw?.trim()
+  return token ? token : undefined
+}
@@ -11,8 +11,8 @@ export class TokenManager {
 
   constructor(private readonly ttlMs: number) {}
 
-  generate(): string {
-    const token = crypto.randomBytes(32).toString("base64url")
+  generate(preset?: string): string {
+    const token = preset ?? crypto.randomBytes(32).toString("base64url")
     this.token = { token, createdAt: Date.now(), consumed: false }
     return token
   }
@@ -0,0 +1,40 @@
+/**
+ * Machine-readable readiness announcement for desktop hosts.
+ * Emitted as a single JSON line on stdout when the host sets AGROFORGE_HANDSHAKE=1.
+ */
+export const HANDSHAKE_ENV = "AGROFORGE_HANDSHAKE" as const
+export const HANDSHAKE_MARKER = "agroforge" as const
+export const HANDSHAKE_PROTOCOL_VERSION = 1 as const
+
+export const SERVER_CAPABILITIES: readonly string[] = ["bootstrap-token", "bootstrap-token-env", "shutdown-api"]
+
+export interface ReadyHandshake {
+  handshake: typeof HANDSHAKE_MARKER
+  protocolVersion: number
+  host: string
+  port: number
+  pid: number
+  url: string
+  serverVersion?: string
+  capabilities: readonly string[]
+}
+
+export function isHandshakeRequested(env: NodeJS.ProcessEnv = process.env): boolean {
+  return env[HANDSHAKE_ENV] === "1"
+}
+
+export function emitReadyHandshake(info: { host: string; port: number; url: string; serverVersion?: string }) {
+  if (!isHandshakeRequested()) return
+
+  const payload: ReadyHandshake = {
+    handshake: HANDSHAKE_MARKER,
+    protocolVersion: HANDSHAKE_PROTOCOL_VERSION,
+    host: info.host,
+    port: info.port,
+    pid: process.pid,
+    url: info.url,
+    serverVersion: info.serverVersion,
+    capabilities: SERVER_CAPABILITIES,
+  }
+  process.stdout.write(`${JSON.stringify(payload)}\n`)
+}
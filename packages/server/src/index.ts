@@ -18,7 +18,12 @@ import { InstanceEventBridge } from "./workspaces/instance-events"
 import { createLogger } from "./logger"
 import { launchInBrowser } from "./launcher"
 import { resolveUi } from "./ui/remote-ui"
-import { AuthManager, BOOTSTRAP_TOKEN_STDOUT_PREFIX, DEFAULT_AUTH_USERNAME } from "./auth/manager"
+import {
+  AuthManager,
+  BOOTSTRAP_TOKEN_STDOUT_PREFIX,
+  DEFAULT_AUTH_USERNAME,
+  takeBootstrapTokenFromEnv,
+} from "./auth/manager"
 import { PidTracker } from "./pid-tracker"
 
 const require = createRequire(import.meta.url)
@@ -200,9 +205,11 @@ async function main() {
     logger.child({ component: "auth" }),
   )
 
+  const presetBootstrapToken = takeBootstrapTokenFromEnv()
   if (options.generateToken) {
-    const token = authManager.issueBootstrapToken()
-    if (token) {
+    const token = authManager.issueBootstrapToken(presetBootstrapToken)
+    // A host that supplied its own token already knows it; never echo secrets to stdout then.
+    if (token && !presetBootstrapToken) {
       console.log(`${BOOTSTRAP_TOKEN_STDOUT_PREFIX}${token}`)
     }
   }
@@ -277,6 +284,10 @@ async function main() {
     uiStaticDir: uiResolution.uiStaticDir ?? DEFAULT_UI_STATIC_DIR,
     uiDevServerUrl: uiResolution.uiDevServerUrl,
     logger,
+    onShutdownRequest: () => {
+      logger.info("Shutdown requested over HTTP")
+      void shutdown()
+    },
   })
 
   const startInfo = await server.start()
@@ -21,12 +21,14 @@ import { registerEventRoutes } from "./routes/events"
 import { registerStorageRoutes } from "./routes/storage"
 import { registerPluginRoutes } from "./routes/plugin"
 import { registerBackgroundProcessRoutes } from "./routes/background-processes"
+import { registerServerRoutes } from "./routes/server"
 import { ServerMeta } from "../api-types"
 import { InstanceStore } from "../storage/instance-store"
 import { BackgroundProcessManager } from "../background-processes/manager"
 import type { AuthManager } from "../auth/manager"
 import { registerAuthRoutes } from "./routes/auth"
 import { sendUnauthorized, wantsHtml } from "../auth/http-auth"
+import { emitReadyHandshake } from "../handshake"
 
 interface HttpServerDeps {
   host: string
@@ -42,6 +44,7 @@ interface HttpServerDeps {
   uiStaticDir: string
   uiDevServerUrl?: string
   logger: Logger
+  onShutdownRequest: () => void
 }
 
 interface HttpServerStartResult {
@@ -231,6 +234,7 @@ export function createHttpServer(deps: HttpServerDeps) {
   registerConfigRoutes(app, { configStore: deps.configStore, binaryRegistry: deps.binaryRegistry })
   registerFilesystemRoutes(app, { fileSystemBrowser: deps.fileSystemBrowser })
   registerMetaRoutes(app, { serverMeta: deps.serverMeta })
+  registerServerRoutes(app, { authManager: deps.authManager, onShutdownRequest: deps.onShutdownRequest })
   registerEventRoutes(app, { eventBus: deps.eventBus, registerClient: registerSseClient, logger: sseLogger })
   registerStorageRoutes(app, {
     instanceStore: deps.instanceStore,
@@ -301,6 +305,12 @@ export function createHttpServer(deps: HttpServerDeps) {
       deps.serverMeta.port = actualPort
       deps.serverMeta.listeningMode = deps.host === "0.0.0.0" || !isLoopbackHost(deps.host) ? "all" : "local"
       deps.logger.info({ port: actualPort, host: deps.host }, "HTTP server listening")
+      emitReadyHandshake({
+        host: deps.host,
+        port: actualPort,
+        url: serverUrl,
+        serverVersion: deps.serverMeta.serverVersion,
+      })
       console.log(`AgroForge Server is ready at ${serverUrl}`)
 
       return { port: actualPort, url: serverUrl, displayHost }
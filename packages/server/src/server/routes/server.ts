@@ -0,0 +1,21 @@
+import { FastifyInstance } from "fastify"
+import type { AuthManager } from "../../auth/manager"
+
+interface RouteDeps {
+  authManager: AuthManager
+  onShutdownRequest: () => void
+}
+
+export function registerServerRoutes(app: FastifyInstance, deps: RouteDeps) {
+  // Lets a desktop host ask for the same graceful shutdown that SIGTERM triggers,
+  // which also works on Windows where there is no catchable termination signal.
+  app.post("/api/server/shutdown", async (request, reply) => {
+    if (!deps.authManager.isLoopbackRequest(request)) {
+      reply.code(403).send({ error: "Shutdown is only allowed from this machine" })
+      return
+    }
+
+    reply.code(202).send({ status: "shutting-down" })
+    setImmediate(() => deps.onShutdownRequest())
+  })
+}
@@ -1,7 +1,8 @@
 #!/usr/bin/env node
 const fs = require("fs")
 const path = require("path")
-const { execSync } = require("child_process")
+const crypto = require("crypto")
+const { execFileSync, execSync } = require("child_process")
 
 const root = path.resolve(__dirname, "..")
 const workspaceRoot = path.resolve(root, "..", "..")
@@ -10,6 +11,7 @@ const uiRoot = path.resolve(root, "..", "ui")
 const uiDist = path.resolve(uiRoot, "src", "renderer", "dist")
 const serverDest = path.resolve(root, "src-tauri", "resources", "server")
 const uiLoadingDest = path.resolve(root, "src-tauri", "resources", "ui-loading")
+const nodeDest = path.resolve(root, "src-tauri", "resources", "node")
 
 const sources = ["dist", "public", "node_modules", "package.json"]
 
@@ -204,6 +206,145 @@ function stripNodeModuleBins() {
   }
 }
 
+function hostTargetTriple() {
+  const triples = {
+    "linux-x64": "x86_64-unknown-linux-gnu",
+    "linux-arm64": "aarch64-unknown-linux-gnu",
+    "darwin-arm64": "aarch64-apple-darwin",
+    "darwin-x64": "x86_64-apple-darwin",
+    "win32-x64": "x86_64-pc-windows-msvc",
+    "win32-arm64": "aarch64-pc-windows-msvc",
+  }
+  return triples[`${process.platform}-${process.arch}`]
+}
+
+// Official Node.js release bundled with the app; AGROFORGE_NODE_VERSION overrides it.
+const DEFAULT_NODE_VERSION = "v22.12.0"
+const nodeCacheDir = path.join(workspaceRoot, "node_modules", ".cache", "agroforge-node")
+
+// nodejs.org build for each target triple: the archive to fetch and the binary inside it.
+// Windows publishes node.exe on its own, so there is nothing to extract.
+function officialNodeBuild(targetTriple, version) {
+  const platforms = {
+    "x86_64-unknown-linux-gnu": "linux-x64",
+    "aarch64-unknown-linux-gnu": "linux-arm64",
+    "x86_64-apple-darwin": "darwin-x64",
+    "aarch64-apple-darwin": "darwin-arm64",
+    "x86_64-pc-windows-msvc": "win-x64",
+    "aarch64-pc-windows-msvc": "win-arm64",
+  }
+  const platform = platforms[targetTriple]
+  if (!platform) {
+    return undefined
+  }
+  if (platform.startsWith("win-")) {
+    return { file: `${platform}/node.exe` }
+  }
+  const dir = `node-${version}-${platform}`
+  return { file: `${dir}.tar.gz`, binary: `${dir}/bin/node`, license: `${dir}/LICENSE` }
+}
+
+async function download(url) {
+  let response
+  try {
+    response = await fetch(url)
+  } catch (error) {
+    throw new Error(
+      `[prebuild] could not download ${url} (${error.message}); set AGROFORGE_NODE_RUNTIME to a self-contained Node binary, or AGROFORGE_BUNDLE_NODE=0 to skip`,
+    )
+  }
+  if (!response.ok) {
+    throw new Error(`[prebuild] ${url} answered HTTP ${response.status}`)
+  }
+  return Buffer.from(await response.arrayBuffer())
+}
+
+// Downloads the official build for the target and checks it against the release's
+// SHASUMS256.txt. Downloads are cached per version, so rebuilds work offline.
+async function fetchOfficialNode(targetTriple) {
+  const version = process.env.AGROFORGE_NODE_VERSION || DEFAULT_NODE_VERSION
+  const build = officialNodeBuild(targetTriple, version)
+  if (!build) {
+    throw new Error(
+      `[prebuild] no official Node.js build for ${targetTriple}; set AGROFORGE_NODE_RUNTIME to the binary to bundle, or AGROFORGE_BUNDLE_NODE=0`,
+    )
+  }
+
+  const baseUrl = `https://nodejs.org/dist/${version}`
+  const cached = path.join(nodeCacheDir, version, build.file)
+  if (!fs.existsSync(cached)) {
+    console.log(`[prebuild] downloading Node ${version} for ${targetTriple}...`)
+    const [archive, sums] = await Promise.all([
+      download(`${baseUrl}/${build.file}`),
+      download(`${baseUrl}/SHASUMS256.txt`),
+    ])
+    const expected = sums
+      .toString("utf8")
+      .split("\n")
+      .map((line) => line.trim().split(/\s+/))
+      .find(([, name]) => name === build.file)?.[0]
+    const actual = crypto.createHash("sha256").update(archive).digest("hex")
+    if (!expected || expected !== actual) {
+      throw new Error(`[prebuild] checksum mismatch for ${build.file} (expected ${expected}, got ${actual})`)
+    }
+    fs.mkdirSync(path.dirname(cached), { recursive: true })
+    fs.writeFileSync(cached, archive)
+  }
+
+  if (!build.binary) {
+    return { binary: cached }
+  }
+  const extracted = path.join(nodeCacheDir, version, targetTriple)
+  const binary = path.join(extracted, build.binary)
+  if (!fs.existsSync(binary)) {
+    fs.mkdirSync(extracted, { recursive: true })
+    execFileSync("tar", ["-xzf", cached, "-C", extracted, build.binary, build.license], { stdio: "inherit" })
+  }
+  return { binary, license: path.join(extracted, build.license) }
+}
+
+// Ships a Node binary so the packaged app runs without a system install. The desktop shell
+// looks for it under resources/node/<target triple>/. The official nodejs.org build for the
+// target is bundled, since a distro or Homebrew node links against libraries the user may
+// not have. Set AGROFORGE_NODE_RUNTIME to bundle a binary of your own instead (it must be
+// self-contained), or AGROFORGE_BUNDLE_NODE=0 to skip.
+async function bundleNodeRuntime() {
+  fs.rmSync(nodeDest, { recursive: true, force: true })
+  fs.mkdirSync(nodeDest, { recursive: true })
+
+  if (process.env.AGROFORGE_BUNDLE_NODE === "0") {
+    console.log("[prebuild] not bundling a Node runtime (AGROFORGE_BUNDLE_NODE=0)")
+    return
+  }
+
+  const targetTriple = process.env.TAURI_ENV_TARGET_TRIPLE || hostTargetTriple()
+  if (!targetTriple) {
+    throw new Error(
+      "[prebuild] unknown target triple; set TAURI_ENV_TARGET_TRIPLE, or AGROFORGE_BUNDLE_NODE=0 to skip the Node runtime",
+    )
+  }
+
+  let runtime
+  if (process.env.AGROFORGE_NODE_RUNTIME) {
+    runtime = { binary: process.env.AGROFORGE_NODE_RUNTIME }
+    if (!fs.existsSync(runtime.binary)) {
+      throw new Error(`[prebuild] Node runtime not found at ${runtime.binary}`)
+    }
+  } else {
+    runtime = await fetchOfficialNode(targetTriple)
+  }
+
+  const binaryName = targetTriple.includes("windows") ? "node.exe" : "node"
+  const dest = path.join(nodeDest, targetTriple, binaryName)
+  fs.mkdirSync(path.dirname(dest), { recursive: true })
+  fs.copyFileSync(runtime.binary, dest)
+  fs.chmodSync(dest, 0o755)
+  if (runtime.license && fs.existsSync(runtime.license)) {
+    fs.copyFileSync(runtime.license, path.join(path.dirname(dest), "LICENSE"))
+  }
+  console.log(`[prebuild] bundled Node ${runtime.binary} -> ${dest}`)
+}
+
 function copyUiLoadingAssets() {
   const loadingSource = path.join(uiDist, "loading.html")
   const assetsSource = path.join(uiDist, "assets")
@@ -223,12 +364,20 @@ function copyUiLoadingAssets() {
   console.log(`[prebuild] prepared UI loading assets from ${uiDist}`)
 }
 
-ensureServerDevDependencies()
-ensureUiDevDependencies()
-ensureRollupPlatformBinary()
-ensureServerDependencies()
-ensureServerBuild()
-ensureUiBuild()
-copyServerArtifacts()
-stripNodeModuleBins()
-copyUiLoadingAssets()
+async function main() {
+  ensureServerDevDependencies()
+  ensureUiDevDependencies()
+  ensureRollupPlatformBinary()
+  ensureServerDependencies()
+  ensureServerBuild()
+  ensureUiBuild()
+  copyServerArtifacts()
+  stripNodeModuleBins()
+  await bundleNodeRuntime()
+  copyUiLoadingAssets()
+}
+
+main().catch((error) => {
+  console.error(error instanceof Error ? error.message : error)
+  process.exit(1)
+})
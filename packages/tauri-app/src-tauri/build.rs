@@ -1,3 +1,31 @@
+/// Every command registered with `generate_handler!` in main.rs. Listing them makes tauri-build
+/// generate an `allow-<command>` permission for each, and commands are then only reachable from
+/// pages whose capability grants them; see permissions/app.toml.
+const APP_COMMANDS: &[&str] = &[
+    "cli_get_status",
+    "cli_restart",
+    "cli_get_history",
+    "cli_get_logs",
+    "cli_get_metrics",
+    "cli_get_runtime_info",
+    "cli_get_log_path",
+    "cli_get_orphans",
+    "cli_resolve_orphan",
+    "profiles_list",
+    "profiles_save",
+    "profiles_connect",
+    "diagnostics_export",
+];
+
 fn main() {
-    tauri_build::build()
+    // Bundled Node runtimes are stored per target triple; see scripts/prebuild.js.
+    println!(
+        "cargo:rustc-env=AGROFORGE_TARGET_TRIPLE={}",
+        std::env::var("TARGET").expect("cargo sets TARGET for build scripts")
+    );
+    tauri_build::try_build(
+        tauri_build::Attributes::new()
+            .app_manifest(tauri_build::AppManifest::new().commands(APP_COMMANDS)),
+    )
+    .expect("failed to run tauri-build")
 }
@@ -1,54 +1,67 @@
-use dirs::home_dir;
+use crate::config::{load_desktop_config, RemoteConfig};
+use crate::handshake::{
+    parse_ready_line, HandshakeError, ReadyHandshake, ReadySignal, HANDSHAKE_ENV,
+};
+use crate::health::{probe, HealthTracker, HealthVerdict};
+use crate::history::{
+    HistorySnapshot, StartupPhase, StatusHistory, StatusTransition, TransitionReason,
+};
+use crate::host::Host;
+use crate::http_client::{HttpClient, HttpError, HttpRequest, HttpResponse};
+use crate::lifecycle::ChildHandle;
+use crate::logs::{
+    self, now_millis, redact_url_fragments, LogBuffer, LogEntry, LogStream, SERVER_TARGET,
+};
+use crate::metrics::{
+    AlertAction, MetricsBuffer, MetricsSampler, ResourceSample, ThresholdTracker,
+};
+use crate::node;
+use crate::orphans::{self, OrphanServer, ServerLock};
+use crate::process_tree::{self, is_alive, ProcessTree};
+use crate::profiles::{resolve_target, ConnectionTarget, LocalTarget};
+use crate::remote;
+use crate::supervisor::{RestartDecision, Supervisor};
+use crate::user_shell::{self, UserShell};
+use log::{debug, error, info, warn};
 use parking_lot::Mutex;
-use regex::Regex;
-use serde::{Deserialize, Serialize};
+use serde::Serialize;
 use serde_json::json;
-use std::collections::VecDeque;
-use std::env;
-use std::ffi::OsStr;
-use std::fs;
-use std::io::{BufRead, BufReader, Read, Write};
-use std::net::TcpStream;
+use std::collections::{HashMap, VecDeque};
+use std::io::{BufRead, BufReader};
 use std::path::PathBuf;
-use std::process::{Child, Command, Stdio};
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::process::{Command, ExitStatus, Stdio};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::sync::Arc;
 use std::thread;
 use std::time::{Duration, Instant};
-use tauri::{webview::cookie::Cookie, AppHandle, Emitter, Manager, Url};
-
-fn log_line(message: &str) {
-    println!("[tauri-cli] {message}");
-}
+use tauri::Url;
 
 fn workspace_root() -> Option<PathBuf> {
-    std::env::current_dir().ok().and_then(|mut dir| {
+    std::env::current_dir().ok().map(|mut dir| {
         for _ in 0..3 {
             if let Some(parent) = dir.parent() {
                 dir = parent.to_path_buf();
             }
         }
-        Some(dir)
+        dir
     })
 }
 
 const SESSION_COOKIE_NAME: &str = "codenomad_session";
-
-fn navigate_main(app: &AppHandle, url: &str) {
-    if let Some(win) = app.webview_windows().get("main") {
-        let mut display = url.to_string();
-        if let Some(hash_index) = display.find('#') {
-            display.replace_range(hash_index + 1.., "[REDACTED]");
-        }
-        log_line(&format!("navigating main to {display}"));
-        if let Ok(parsed) = Url::parse(url) {
-            let _ = win.navigate(parsed);
-        } else {
-            log_line("failed to parse URL for navigation");
-        }
-    } else {
-        log_line("main window not found for navigation");
-    }
+/// The server adopts this token instead of generating one and printing it to stdout.
+const BOOTSTRAP_TOKEN_ENV: &str = "AGROFORGE_BOOTSTRAP_TOKEN";
+/// Older servers still announce their own token on stdout with this prefix.
+const BOOTSTRAP_TOKEN_PREFIX: &str = "AGROFORGE_BOOTSTRAP_TOKEN:";
+/// How long `stop` waits for the reaper after a hard kill before giving up on it.
+const KILL_WAIT: Duration = Duration::from_secs(5);
+/// Handshake capability advertised by servers that expose `POST /api/server/shutdown`.
+const SHUTDOWN_CAPABILITY: &str = "shutdown-api";
+
+fn generate_bootstrap_token() -> anyhow::Result<String> {
+    let mut bytes = [0u8; 32];
+    getrandom::getrandom(&mut bytes)
+        .map_err(|err| anyhow::anyhow!("failed to generate bootstrap token: {err}"))?;
+    Ok(bytes.iter().map(|byte| format!("{byte:02x}")).collect())
 }
 
 fn extract_cookie_value(set_cookie: &str, name: &str) -> Option<String> {
@@ -64,148 +77,114 @@ fn extract_cookie_value(set_cookie: &str, name: &str) -> Option<String> {
     Some(value.to_string())
 }
 
-fn exchange_bootstrap_token(base_url: &str, token: &str) -> anyhow::Result<Option<String>> {
-    let parsed = Url::parse(base_url)?;
-    let host = parsed.host_str().unwrap_or("127.0.0.1");
-    let port = parsed.port_or_known_default().unwrap_or(80);
-
-    // This is only used for local bootstrap; we assume plain HTTP.
-    let mut stream = TcpStream::connect((host, port))?;
-
-    let body = format!("{{\"token\":\"{}\"}}", token);
-    let request = format!(
-        "POST /api/auth/token HTTP/1.1\r\nHost: {host}:{port}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
-        body.as_bytes().len(),
-        body
-    );
-
-    stream.write_all(request.as_bytes())?;
-    stream.flush()?;
-
-    let mut response = String::new();
-    stream.read_to_string(&mut response)?;
-
-    let (raw_headers, _rest) = response
-        .split_once("\r\n\r\n")
-        .or_else(|| response.split_once("\n\n"))
-        .unwrap_or((response.as_str(), ""));
-
-    let mut lines = raw_headers.lines();
-    let status_line = lines.next().unwrap_or("");
-    if !status_line.contains(" 200 ") {
-        return Ok(None);
-    }
-
-    for line in lines {
-        // handle case-insensitive header name
-        if let Some(value) = line.strip_prefix("Set-Cookie:") {
-            if let Some(session_id) = extract_cookie_value(value.trim(), SESSION_COOKIE_NAME) {
-                return Ok(Some(session_id));
-            }
-        } else if let Some(value) = line.strip_prefix("set-cookie:") {
-            if let Some(session_id) = extract_cookie_value(value.trim(), SESSION_COOKIE_NAME) {
-                return Ok(Some(session_id));
-            }
-        }
-    }
-
-    Ok(None)
-}
-
-fn set_session_cookie(app: &AppHandle, base_url: &str, session_id: &str) -> anyhow::Result<()> {
-    let parsed = Url::parse(base_url)?;
-    let domain = parsed.host_str().unwrap_or("127.0.0.1").to_string();
-
-    let cookie = Cookie::build((SESSION_COOKIE_NAME, session_id))
-        .domain(domain)
-        .path("/")
-        .http_only(true)
-        .same_site(tauri::webview::cookie::SameSite::Lax)
-        .build();
-
-    if let Some(win) = app.webview_windows().get("main") {
-        win.set_cookie(cookie)?;
-    }
-
-    Ok(())
+fn exchange_bootstrap_token(base_url: &str, token: &str) -> Result<Option<String>, HttpError> {
+    let response = HttpClient::new().post_json(
+        &format!("{base_url}/api/auth/token"),
+        &json!({ "token": token }),
+    )?;
+    Ok(session_from_response(&response))
 }
 
-const DEFAULT_CONFIG_PATH: &str = "~/.config/codenomad/config.json";
-
-#[derive(Debug, Deserialize)]
-struct PreferencesConfig {
-    #[serde(rename = "listeningMode")]
-    listening_mode: Option<String>,
+fn login_with_password(
+    base_url: &str,
+    username: &str,
+    password: &str,
+) -> Result<Option<String>, HttpError> {
+    let response = HttpClient::new().post_json(
+        &format!("{base_url}/api/auth/login"),
+        &json!({ "username": username, "password": password }),
+    )?;
+    Ok(session_from_response(&response))
 }
 
-#[derive(Debug, Deserialize)]
-struct AppConfig {
-    preferences: Option<PreferencesConfig>,
-}
-
-fn resolve_config_path() -> PathBuf {
-    let raw = env::var("CLI_CONFIG")
-        .ok()
-        .filter(|value| !value.trim().is_empty())
-        .unwrap_or_else(|| DEFAULT_CONFIG_PATH.to_string());
-    expand_home(&raw)
-}
-
-fn expand_home(path: &str) -> PathBuf {
-    if path.starts_with("~/") {
-        if let Some(home) = home_dir().or_else(|| env::var("HOME").ok().map(PathBuf::from)) {
-            return home.join(path.trim_start_matches("~/"));
-        }
-    }
-    PathBuf::from(path)
-}
-
-fn resolve_listening_mode() -> String {
-    let path = resolve_config_path();
-    if let Ok(content) = fs::read_to_string(path) {
-        if let Ok(config) = serde_json::from_str::<AppConfig>(&content) {
-            if let Some(mode) = config
-                .preferences
-                .as_ref()
-                .and_then(|prefs| prefs.listening_mode.as_ref())
-            {
-                if mode == "local" {
-                    return "local".to_string();
-                }
-                if mode == "all" {
-                    return "all".to_string();
-                }
-            }
-        }
+fn session_from_response(response: &HttpResponse) -> Option<String> {
+    if !response.is_success() {
+        return None;
     }
-    "local".to_string()
+    response
+        .headers("set-cookie")
+        .find_map(|value| extract_cookie_value(value, SESSION_COOKIE_NAME))
 }
 
-fn resolve_listening_host() -> String {
-    let mode = resolve_listening_mode();
-    if mode == "local" {
-        "127.0.0.1".to_string()
-    } else {
-        "0.0.0.0".to_string()
-    }
+fn set_session_cookie(host: &Host, base_url: &str, session_id: &str) -> anyhow::Result<()> {
+    host.set_cookie(base_url, SESSION_COOKIE_NAME, session_id)
 }
 
+/// Lifecycle of the server. Every change goes through `CliProcessManager::set_state`, which
+/// refuses moves `can_transition_to` does not allow, so a late event from a superseded spawn
+/// cannot drag the state backwards.
+///
+/// ```text
+/// Stopped ─► Starting ─► Ready ◄─► Degraded ◄─► Unresponsive
+///    ▲          │  ▲        │            │
+///    │          ▼  │        ▼            ▼
+///    │        Error ◄── Restarting ◄─────┘
+///    └── Stopping ◄── (any state with a process)
+/// ```
 #[derive(Debug, Clone, Serialize, PartialEq, Eq)]
 #[serde(rename_all = "lowercase")]
 pub enum CliState {
     Starting,
     Ready,
+    Degraded,
+    Unresponsive,
+    Restarting,
+    Stopping,
     Error,
     Stopped,
 }
 
+impl CliState {
+    /// True once the server has announced readiness, regardless of its current health.
+    pub fn is_running(&self) -> bool {
+        matches!(
+            self,
+            CliState::Ready | CliState::Degraded | CliState::Unresponsive
+        )
+    }
+
+    /// Whether the lifecycle allows moving from `self` to `to`. `stop` can always finish.
+    pub fn can_transition_to(&self, to: &CliState) -> bool {
+        use CliState::*;
+        match to {
+            Stopped => true,
+            Stopping => *self != Stopped,
+            Starting => matches!(self, Stopped | Error | Restarting),
+            // Adopting an orphan goes straight from `Stopped` to `Ready`.
+            Ready => matches!(self, Starting | Stopped) || self.is_running(),
+            Degraded | Unresponsive => self.is_running(),
+            Restarting => *self == Starting || self.is_running(),
+            Error => matches!(self, Starting | Restarting) || self.is_running(),
+        }
+    }
+}
+
+/// Step of a staged `stop`, reported while the state is `Stopping`.
+#[derive(Debug, Clone, Copy, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum ShutdownStage {
+    /// The server was asked over HTTP to persist workspaces and exit on its own.
+    SavingSessions,
+    Terminating,
+    Killing,
+}
+
 #[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
 pub struct CliStatus {
     pub state: CliState,
     pub pid: Option<u32>,
     pub port: Option<u16>,
     pub url: Option<String>,
     pub error: Option<String>,
+    /// Supervisor attempt that produced the current process, if it was an automatic restart.
+    pub restart_attempt: Option<u32>,
+    /// Unix time in milliseconds at which the next automatic restart will be launched.
+    pub next_retry_at: Option<u64>,
+    pub server_version: Option<String>,
+    /// Features the server advertised in its readiness handshake.
+    pub capabilities: Vec<String>,
+    pub shutdown_stage: Option<ShutdownStage>,
 }
 
 impl Default for CliStatus {
@@ -216,184 +195,636 @@ impl Default for CliStatus {
             port: None,
             url: None,
             error: None,
+            restart_attempt: None,
+            next_retry_at: None,
+            server_version: None,
+            capabilities: Vec::new(),
+            shutdown_stage: None,
         }
     }
 }
 
+/// Works out what to spawn for a local server. `CliEntry::resolve` outside of tests, which
+/// substitute a scripted stand-in.
+#[derive(Clone)]
+struct EntryResolver(Arc<dyn Fn(bool) -> anyhow::Result<CliEntry> + Send + Sync>);
+
+impl std::fmt::Debug for EntryResolver {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("EntryResolver")
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct CliProcessManager {
     status: Arc<Mutex<CliStatus>>,
-    child: Arc<Mutex<Option<Child>>>,
+    /// The spawned server, if any. Its `Child` is owned by the handle's reaper thread.
+    child: Arc<Mutex<Option<ChildHandle>>>,
+    /// Process group (Job Object on Windows) holding the server and its descendants.
+    tree: Arc<Mutex<Option<ProcessTree>>>,
     ready: Arc<AtomicBool>,
     bootstrap_token: Arc<Mutex<Option<String>>>,
+    /// Session obtained from the token exchange; authenticates the shutdown request.
+    session_id: Arc<Mutex<Option<String>>>,
+    /// Mirrors the lock file that lets the next app launch find this server if we crash.
+    server_lock: Arc<Mutex<Option<ServerLock>>>,
+    /// How the last `start` asked for the server to be spawned; reused by supervised restarts.
+    local: Arc<Mutex<LocalTarget>>,
+    /// Handle from the last `start`, so `stop` can report its progress.
+    host: Arc<Mutex<Option<Host>>>,
+    /// Origin of the attached remote server, so `stop` can revoke the window's access to it.
+    remote_origin: Arc<Mutex<Option<String>>>,
+    supervisor: Arc<Mutex<Supervisor>>,
+    /// Bumped by every `stop`, so exit handlers and pending restarts can tell they were superseded.
+    run_id: Arc<AtomicU64>,
+    /// Bumped by every spawn and `stop`, so a startup watchdog only ever acts on its own child.
+    spawn_generation: Arc<AtomicU64>,
+    /// Recent server output, kept across restarts so a failed start can still be explained.
+    logs: Arc<Mutex<LogBuffer>>,
+    history: Arc<Mutex<StatusHistory>>,
+    metrics: Arc<Mutex<MetricsBuffer>>,
+    resolve_entry: EntryResolver,
 }
 
 impl CliProcessManager {
     pub fn new() -> Self {
+        Self::with_entry_resolver(CliEntry::resolve)
+    }
+
+    fn with_entry_resolver(
+        resolve: impl Fn(bool) -> anyhow::Result<CliEntry> + Send + Sync + 'static,
+    ) -> Self {
         Self {
             status: Arc::new(Mutex::new(CliStatus::default())),
             child: Arc::new(Mutex::new(None)),
+            tree: Arc::new(Mutex::new(None)),
             ready: Arc::new(AtomicBool::new(false)),
             bootstrap_token: Arc::new(Mutex::new(None)),
+            session_id: Arc::new(Mutex::new(None)),
+            server_lock: Arc::new(Mutex::new(None)),
+            local: Arc::new(Mutex::new(LocalTarget::default())),
+            host: Arc::new(Mutex::new(None)),
+            remote_origin: Arc::new(Mutex::new(None)),
+            supervisor: Arc::new(Mutex::new(Supervisor::new(
+                load_desktop_config().supervisor,
+            ))),
+            run_id: Arc::new(AtomicU64::new(0)),
+            spawn_generation: Arc::new(AtomicU64::new(0)),
+            logs: Arc::new(Mutex::new(LogBuffer::new())),
+            history: Arc::new(Mutex::new(StatusHistory::default())),
+            metrics: Arc::new(Mutex::new(MetricsBuffer::default())),
+            resolve_entry: EntryResolver(Arc::new(resolve)),
         }
     }
 
-    pub fn start(&self, app: AppHandle, dev: bool) -> anyhow::Result<()> {
-        log_line(&format!("start requested (dev={dev})"));
+    pub fn start(&self, host: Host, dev: bool) -> anyhow::Result<()> {
+        self.start_with(host, dev, TransitionReason::Start)
+    }
+
+    /// Like `start`, but recorded in the history as the user's doing.
+    pub fn restart(&self, host: Host, dev: bool) -> anyhow::Result<()> {
+        self.start_with(host, dev, TransitionReason::UserRestart)
+    }
+
+    fn start_with(&self, host: Host, dev: bool, reason: TransitionReason) -> anyhow::Result<()> {
+        info!(dev; "start requested");
+        *self.host.lock() = Some(host.clone());
         self.stop()?;
+        let config = load_desktop_config();
+        let target = resolve_target(&config);
+        self.supervisor.lock().reset(config.supervisor);
+        let run_id = self.run_id.load(Ordering::SeqCst);
+        match target {
+            ConnectionTarget::Remote(remote) => self.attach(host, remote, run_id, reason),
+            ConnectionTarget::Local(local) => {
+                *self.local.lock() = local;
+                self.launch(host, dev, run_id, reason);
+            }
+        }
+        Ok(())
+    }
+
+    fn launch(&self, host: Host, dev: bool, run_id: u64, reason: TransitionReason) {
         self.ready.store(false, Ordering::SeqCst);
         *self.bootstrap_token.lock() = None;
+        *self.session_id.lock() = None;
+        self.history.lock().begin_startup(now_millis());
+        {
+            let mut status = self.status.lock();
+            self.set_state(&mut status, CliState::Starting, reason);
+            status.port = None;
+            status.url = None;
+            status.error = None;
+            status.pid = None;
+            status.next_retry_at = None;
+            status.server_version = None;
+            status.capabilities.clear();
+        }
+        Self::emit_status(&host, &self.status.lock());
+
+        let manager = self.clone();
+        thread::spawn(move || {
+            if let Err(err) = manager.spawn_cli(host.clone(), dev, run_id) {
+                error!(error:% = err; "cli spawn failed");
+                let mut locked = manager.status.lock();
+                manager.set_state(
+                    &mut locked,
+                    CliState::Error,
+                    TransitionReason::SpawnFailed {
+                        message: err.to_string(),
+                    },
+                );
+                locked.error = Some(err.to_string());
+                let snapshot = locked.clone();
+                drop(locked);
+                host.emit("cli:error", json!({"message": err.to_string()}));
+                host.emit("cli:status", snapshot);
+            }
+        });
+    }
+
+    /// Connects to a server running elsewhere instead of spawning one. Nothing is supervised;
+    /// `stop` only detaches from it.
+    fn attach(&self, host: Host, remote: RemoteConfig, run_id: u64, reason: TransitionReason) {
+        self.ready.store(false, Ordering::SeqCst);
+        *self.session_id.lock() = None;
         {
             let mut status = self.status.lock();
-            status.state = CliState::Starting;
+            self.set_state(&mut status, CliState::Starting, reason);
             status.port = None;
             status.url = None;
             status.error = None;
             status.pid = None;
+            status.server_version = None;
+            status.capabilities.clear();
         }
-        Self::emit_status(&app, &self.status.lock());
+        Self::emit_status(&host, &self.status.lock());
 
-        let status_arc = self.status.clone();
-        let child_arc = self.child.clone();
-        let ready_flag = self.ready.clone();
-        let token_arc = self.bootstrap_token.clone();
+        let manager = self.clone();
         thread::spawn(move || {
-            if let Err(err) = Self::spawn_cli(
-                app.clone(),
-                status_arc.clone(),
-                child_arc,
-                ready_flag,
-                token_arc,
-                dev,
-            ) {
-                log_line(&format!("cli spawn failed: {err}"));
-                let mut locked = status_arc.lock();
-                locked.state = CliState::Error;
+            if let Err(err) = manager.connect_remote(&host, &remote, run_id) {
+                error!(error:% = err; "attaching to remote server failed");
+                let mut locked = manager.status.lock();
+                manager.set_state(
+                    &mut locked,
+                    CliState::Error,
+                    TransitionReason::ConnectFailed {
+                        message: err.to_string(),
+                    },
+                );
                 locked.error = Some(err.to_string());
                 let snapshot = locked.clone();
                 drop(locked);
-                let _ = app.emit("cli:error", json!({"message": err.to_string()}));
-                let _ = app.emit("cli:status", snapshot);
+                host.emit("cli:error", json!({"message": err.to_string()}));
+                host.emit("cli:status", snapshot);
             }
         });
+    }
+
+    fn connect_remote(
+        &self,
+        host: &Host,
+        remote: &RemoteConfig,
+        run_id: u64,
+    ) -> anyhow::Result<()> {
+        let url = Url::parse(remote.url.trim())
+            .map_err(|err| anyhow::anyhow!("Invalid remote server URL {}: {err}", remote.url))?;
+        let origin = remote::origin_of(&url)
+            .ok_or_else(|| anyhow::anyhow!("Remote server URL {} has no host", remote.url))?;
+        let base_url = url.as_str().trim_end_matches('/').to_string();
+        info!(url = base_url.as_str(); "attaching to remote server");
+        {
+            // Granted under the lock, so a concurrent `stop` either revokes it or makes us skip it.
+            let mut granted = self.remote_origin.lock();
+            if self.run_id.load(Ordering::SeqCst) != run_id {
+                debug!("remote attach superseded by stop/start");
+                return Ok(());
+            }
+            host.allow_origin(&origin)?;
+            *granted = Some(origin);
+        }
+
+        let health = load_desktop_config().health;
+        match probe(
+            &base_url,
+            &health.path,
+            Duration::from_millis(health.timeout_ms),
+        ) {
+            Ok(code) if code < 500 => {}
+            Ok(code) => anyhow::bail!("Remote server {base_url} answered HTTP {code}"),
+            Err(err) => anyhow::bail!("Remote server {base_url} is not reachable: {err}"),
+        }
+
+        let mut session_id = None;
+        if let (Some(username), Some(password)) = (remote.username.as_deref(), remote.password()) {
+            if remote::may_send_credentials(&url) {
+                session_id = login_with_password(&base_url, username, &password)?;
+                if session_id.is_none() {
+                    warn!("remote server rejected the saved credentials");
+                }
+            } else {
+                warn!(
+                    url = base_url.as_str();
+                    "not sending the saved password over plain http; sign in through the login page or use https"
+                );
+            }
+        }
+
+        if self.run_id.load(Ordering::SeqCst) != run_id {
+            debug!("remote attach superseded by stop/start");
+            return Ok(());
+        }
+
+        self.ready.store(true, Ordering::SeqCst);
+        let mut locked = self.status.lock();
+        self.set_state(&mut locked, CliState::Ready, TransitionReason::Ready);
+        locked.port = url.port_or_known_default();
+        locked.url = Some(base_url.clone());
+        locked.error = None;
+
+        match session_id {
+            Some(session_id) => {
+                *self.session_id.lock() = Some(session_id.clone());
+                if let Err(err) = set_session_cookie(host, &base_url, &session_id) {
+                    warn!(error:% = err; "failed to set session cookie");
+                    host.navigate_main(&format!("{base_url}/login"));
+                } else {
+                    host.navigate_main(&base_url);
+                }
+            }
+            None => host.navigate_main(&format!("{base_url}/login")),
+        }
+        host.emit("cli:ready", locked.clone());
+        Self::emit_status(host, &locked);
+        drop(locked);
 
+        self.spawn_health_monitor(host.clone(), run_id, None);
         Ok(())
     }
 
+    /// Stops the server in stages: an HTTP shutdown request so it can save its workspaces,
+    /// then `SIGTERM`, then a hard kill, each after the grace period from `desktop.json`.
     pub fn stop(&self) -> anyhow::Result<()> {
-        let mut child_opt = self.child.lock();
-        if let Some(mut child) = child_opt.take() {
-            #[cfg(unix)]
-            unsafe {
-                libc::kill(child.id() as i32, libc::SIGTERM);
+        self.run_id.fetch_add(1, Ordering::SeqCst);
+        self.spawn_generation.fetch_add(1, Ordering::SeqCst);
+        let config = load_desktop_config().shutdown;
+        let pid = self.tree.lock().as_ref().map(ProcessTree::pid);
+
+        if let Some(pid) = pid {
+            info!(pid; "stopping cli");
+            let (url, supports_api) = {
+                let status = self.status.lock();
+                (
+                    status.url.clone(),
+                    status.state.is_running()
+                        && status.capabilities.iter().any(|c| c == SHUTDOWN_CAPABILITY),
+                )
+            };
+
+            let mut exited = false;
+            if let Some(url) = url.filter(|_| config.use_api && supports_api) {
+                self.set_shutdown_stage(ShutdownStage::SavingSessions);
+                let session_id = self.session_id.lock().clone();
+                match request_api_shutdown(&url, session_id.as_deref()) {
+                    Ok(()) => {
+                        exited =
+                            self.wait_for_exit(pid, Duration::from_millis(config.api_grace_ms));
+                        if !exited {
+                            warn!(pid; "server did not exit after shutdown request");
+                        }
+                    }
+                    Err(err) => warn!(error:% = err; "shutdown request failed"),
+                }
             }
-            #[cfg(windows)]
+
+            // Windows has no catchable termination signal, so it goes straight to the kill.
+            #[cfg(unix)]
             {
-                let _ = child.kill();
+                if !exited {
+                    self.set_shutdown_stage(ShutdownStage::Terminating);
+                    if let Some(tree) = self.tree.lock().as_ref() {
+                        tree.terminate();
+                    }
+                    exited = self.wait_for_exit(pid, Duration::from_millis(config.term_grace_ms));
+                }
             }
 
-            let start = Instant::now();
-            loop {
-                match child.try_wait() {
-                    Ok(Some(_)) => break,
-                    Ok(None) => {
-                        if start.elapsed() > Duration::from_secs(4) {
-                            #[cfg(unix)]
-                            unsafe {
-                                libc::kill(child.id() as i32, libc::SIGKILL);
-                            }
-                            #[cfg(windows)]
-                            {
-                                let _ = child.kill();
-                            }
-                            break;
-                        }
-                        thread::sleep(Duration::from_millis(50));
+            if !exited {
+                self.set_shutdown_stage(ShutdownStage::Killing);
+                self.kill_tree(pid);
+                let child = self.child.lock().clone();
+                if let Some(child) = child.filter(|child| child.pid() == pid) {
+                    child.kill();
+                    if !child.wait_timeout(KILL_WAIT) {
+                        warn!(pid; "server still not reaped after kill");
                     }
-                    Err(_) => break,
                 }
             }
+            // Even after a clean exit, take down anything the server left behind.
+            self.release_tree(pid);
+            self.clear_server_lock(pid);
+        }
+        self.child.lock().take();
+        if let Some(origin) = self.remote_origin.lock().take() {
+            if let Some(host) = self.host.lock().clone() {
+                host.revoke_origin(&origin);
+            }
         }
 
         let mut status = self.status.lock();
-        status.state = CliState::Stopped;
-        status.pid = None;
-        status.port = None;
-        status.url = None;
+        let was_stopping = status.state == CliState::Stopping;
+        self.set_state(&mut status, CliState::Stopped, TransitionReason::Stopped);
+        Self::clear_endpoint(&mut status);
         status.error = None;
+        status.restart_attempt = None;
+        status.next_retry_at = None;
+        status.server_version = None;
+        status.capabilities.clear();
+        status.shutdown_stage = None;
+        if was_stopping {
+            if let Some(host) = self.host.lock().as_ref() {
+                Self::emit_status(host, &status);
+            }
+        }
 
         Ok(())
     }
 
+    fn set_shutdown_stage(&self, stage: ShutdownStage) {
+        debug!(stage:? = stage; "shutdown stage");
+        let mut status = self.status.lock();
+        self.set_state(
+            &mut status,
+            CliState::Stopping,
+            TransitionReason::StopRequested,
+        );
+        status.shutdown_stage = Some(stage);
+        if let Some(host) = self.host.lock().as_ref() {
+            Self::emit_status(host, &status);
+        }
+    }
+
+    /// Waits until the server has exited. Adopted servers are not our children, so for
+    /// them only the pid can be polled.
+    fn wait_for_exit(&self, pid: u32, timeout: Duration) -> bool {
+        // Clone the handle so the wait does not hold the lock.
+        let child = self.child.lock().clone();
+        match child.filter(|child| child.pid() == pid) {
+            Some(child) => child.wait_timeout(timeout),
+            None => wait_until(timeout, || !is_alive(pid)),
+        }
+    }
+
+    pub fn logs(
+        &self,
+        since: Option<u64>,
+        limit: Option<usize>,
+        stream: Option<LogStream>,
+    ) -> Vec<LogEntry> {
+        self.logs.lock().query(since, limit, stream)
+    }
+
+    /// Servers left running by a previous app run that crashed or was killed.
+    pub fn orphans(&self) -> Vec<OrphanServer> {
+        orphans::find_orphans()
+            .iter()
+            .map(OrphanServer::from)
+            .collect()
+    }
+
+    /// Shuts an orphaned server down the same way `stop` would: over its API first,
+    /// then with signals.
+    pub fn reap_orphan(&self, pid: u32) -> anyhow::Result<()> {
+        let lock = orphans::find_orphan(pid)
+            .ok_or_else(|| anyhow::anyhow!("No orphaned server with pid {pid}"))?;
+        info!(pid; "reaping orphaned server");
+        let config = load_desktop_config().shutdown;
+        let tree = ProcessTree::adopt(pid);
+
+        let mut exited = false;
+        let supports_api = lock.capabilities.iter().any(|c| c == SHUTDOWN_CAPABILITY);
+        if let Some(url) = lock
+            .url
+            .as_deref()
+            .filter(|_| config.use_api && supports_api)
+        {
+            match request_api_shutdown(url, lock.session_id.as_deref()) {
+                Ok(()) => {
+                    exited = wait_until(Duration::from_millis(config.api_grace_ms), || {
+                        !is_alive(pid)
+                    });
+                }
+                Err(err) => warn!(pid, error:% = err; "shutdown request failed"),
+            }
+        }
+        #[cfg(unix)]
+        {
+            if !exited {
+                tree.terminate();
+                exited = wait_until(Duration::from_millis(config.term_grace_ms), || {
+                    !is_alive(pid)
+                });
+            }
+        }
+        if !exited {
+            warn!(pid; "orphaned server ignored shutdown; killing");
+        }
+        tree.kill();
+        orphans::remove_lock(lock.app_pid);
+        Ok(())
+    }
+
+    /// Takes over an orphaned server instead of starting a new one.
+    pub fn adopt_orphan(&self, host: Host, dev: bool, pid: u32) -> anyhow::Result<()> {
+        let lock = orphans::find_orphan(pid)
+            .ok_or_else(|| anyhow::anyhow!("No orphaned server with pid {pid}"))?;
+        let base_url = lock
+            .url
+            .clone()
+            .ok_or_else(|| anyhow::anyhow!("Server pid {pid} never finished starting"))?;
+        let health = load_desktop_config().health;
+        match probe(
+            &base_url,
+            &health.path,
+            Duration::from_millis(health.timeout_ms),
+        ) {
+            Ok(code) if code < 500 => {}
+            Ok(code) => anyhow::bail!("Server at {base_url} answered HTTP {code}"),
+            Err(err) => anyhow::bail!("Server at {base_url} is not responding: {err}"),
+        }
+
+        info!(pid, url = base_url.as_str(); "adopting orphaned server");
+        *self.host.lock() = Some(host.clone());
+        self.stop()?;
+        self.supervisor
+            .lock()
+            .reset(load_desktop_config().supervisor);
+        let run_id = self.run_id.load(Ordering::SeqCst);
+
+        *self.tree.lock() = Some(ProcessTree::adopt(pid));
+        *self.session_id.lock() = lock.session_id.clone();
+        self.ready.store(true, Ordering::SeqCst);
+        orphans::remove_lock(lock.app_pid);
+        self.write_server_lock(ServerLock {
+            app_pid: std::process::id(),
+            ..lock.clone()
+        });
+
+        {
+            let mut status = self.status.lock();
+            status.pid = Some(pid);
+            self.set_state(&mut status, CliState::Ready, TransitionReason::Adopted);
+            status.port = lock.port;
+            status.url = Some(base_url.clone());
+            status.error = None;
+            status.server_version = lock.server_version.clone();
+            status.capabilities = lock.capabilities.clone();
+            host.emit("cli:ready", status.clone());
+            Self::emit_status(&host, &status);
+        }
+
+        match lock.session_id.as_deref() {
+            Some(session_id) if set_session_cookie(&host, &base_url, session_id).is_ok() => {
+                host.navigate_main(&base_url)
+            }
+            _ => host.navigate_main(&format!("{base_url}/login")),
+        }
+
+        self.spawn_health_monitor(host.clone(), run_id, Some(pid));
+        self.spawn_metrics_monitor(host.clone(), run_id, pid);
+
+        // Not our child, so there is nothing to wait on; watch the pid instead.
+        let manager = self.clone();
+        let adopted_at = Instant::now();
+        thread::spawn(move || {
+            while is_alive(pid) {
+                if manager.run_id.load(Ordering::SeqCst) != run_id {
+                    return;
+                }
+                thread::sleep(Duration::from_millis(500));
+            }
+            manager.release_tree(pid);
+            manager.clear_server_lock(pid);
+            manager.handle_exit(&host, dev, run_id, None, adopted_at.elapsed());
+        });
+
+        Ok(())
+    }
+
+    fn write_server_lock(&self, lock: ServerLock) {
+        if let Err(err) = orphans::write_lock(&lock) {
+            warn!(error:% = err; "failed to write server lock");
+        }
+        *self.server_lock.lock() = Some(lock);
+    }
+
+    fn update_server_lock(&self, update: impl FnOnce(&mut ServerLock)) {
+        let mut guard = self.server_lock.lock();
+        if let Some(lock) = guard.as_mut() {
+            update(lock);
+            if let Err(err) = orphans::write_lock(lock) {
+                warn!(error:% = err; "failed to update server lock");
+            }
+        }
+    }
+
+    fn clear_server_lock(&self, pid: u32) {
+        let mut guard = self.server_lock.lock();
+        if guard.as_ref().is_some_and(|lock| lock.server_pid == pid) {
+            if let Some(lock) = guard.take() {
+                orphans::remove_lock(lock.app_pid);
+            }
+        }
+    }
+
     pub fn status(&self) -> CliStatus {
         self.status.lock().clone()
     }
 
-    fn spawn_cli(
-        app: AppHandle,
-        status: Arc<Mutex<CliStatus>>,
-        child_holder: Arc<Mutex<Option<Child>>>,
-        ready: Arc<AtomicBool>,
-        bootstrap_token: Arc<Mutex<Option<String>>>,
-        dev: bool,
-    ) -> anyhow::Result<()> {
-        log_line("resolving CLI entry");
-        let resolution = CliEntry::resolve(&app, dev)?;
-        let host = resolve_listening_host();
-        log_line(&format!(
-            "resolved CLI entry runner={:?} entry={} host={}",
-            resolution.runner, resolution.entry, host
-        ));
-        let args = resolution.build_args(dev, &host);
-        log_line(&format!("CLI args: {:?}", args));
+    fn spawn_cli(&self, host: Host, dev: bool, run_id: u64) -> anyhow::Result<()> {
+        let generation = self.spawn_generation.fetch_add(1, Ordering::SeqCst) + 1;
+        let status = self.status.clone();
+        let bootstrap_token = self.bootstrap_token.clone();
+
+        debug!("resolving CLI entry");
+        let resolution = (self.resolve_entry.0)(dev)?;
+        let local = self.local.lock().clone();
+        let bind_host = local.listening_mode.host();
+        info!(
+            runner:? = resolution.runner,
+            entry = resolution.entry.as_str(),
+            host = bind_host;
+            "resolved CLI entry"
+        );
+        let args = resolution.build_args(dev, bind_host, local.workspace_root.as_deref());
+        debug!(args:? = args; "CLI args");
         if dev {
-            log_line("development mode: will prefer tsx + source if present");
+            debug!("development mode: will prefer tsx + source if present");
         }
 
         let cwd = workspace_root();
         if let Some(ref c) = cwd {
-            log_line(&format!("using cwd={}", c.display()));
+            debug!(cwd:% = c.display(); "using working directory");
         }
 
-        let command_info = if supports_user_shell() {
-            log_line("spawning via user shell");
-            ShellCommandType::UserShell(build_shell_command_string(&resolution, &args)?)
-        } else {
-            log_line("spawning directly with node");
-            ShellCommandType::Direct(DirectCommand {
-                program: resolution.node_binary.clone(),
-                args: resolution.runner_args(&args),
-            })
-        };
-
-        if !supports_user_shell() {
-            if which::which(&resolution.node_binary).is_err() {
-                return Err(anyhow::anyhow!(
-                    "Node binary not found. Make sure Node.js is installed."
-                ));
+        let token = generate_bootstrap_token()?;
+        *bootstrap_token.lock() = Some(token.clone());
+
+        // Node runs with the login shell's environment so it sees the user's PATH and
+        // version manager setup. Going through the shell is only the fallback for when that
+        // environment could not be captured.
+        let command_info = match user_shell::login_env() {
+            Some(env) => {
+                debug!("spawning directly with node and the login shell environment");
+                ShellCommandType::Direct(DirectCommand {
+                    program: resolution.node_binary.clone(),
+                    args: resolution.runner_args(&args),
+                    env: env.clone(),
+                })
             }
-        }
+            None if supports_user_shell() => {
+                warn!("login shell environment unavailable; spawning via user shell");
+                ShellCommandType::UserShell(build_shell_command_string(&resolution, &args)?)
+            }
+            None => {
+                debug!("spawning directly with node");
+                ShellCommandType::Direct(DirectCommand {
+                    program: resolution.node_binary.clone(),
+                    args: resolution.runner_args(&args),
+                    env: HashMap::new(),
+                })
+            }
+        };
 
-        let child = match &command_info {
+        let mut child = match &command_info {
             ShellCommandType::UserShell(cmd) => {
-                log_line(&format!("spawn command: {} {:?}", cmd.shell, cmd.args));
+                debug!(program = cmd.shell.as_str(), args:? = cmd.args; "spawn command");
                 let mut c = Command::new(&cmd.shell);
                 c.args(&cmd.args)
                     .env("ELECTRON_RUN_AS_NODE", "1")
+                    .env(HANDSHAKE_ENV, "1")
+                    .env(BOOTSTRAP_TOKEN_ENV, &token)
+                    .stdin(Stdio::null())
                     .stdout(Stdio::piped())
                     .stderr(Stdio::piped());
+                process_tree::isolate(&mut c);
                 if let Some(ref cwd) = cwd {
                     c.current_dir(cwd);
                 }
                 c.spawn()?
             }
             ShellCommandType::Direct(cmd) => {
-                log_line(&format!("spawn command: {} {:?}", cmd.program, cmd.args));
+                debug!(program = cmd.program.as_str(), args:? = cmd.args; "spawn command");
                 let mut c = Command::new(&cmd.program);
                 c.args(&cmd.args)
+                    .envs(&cmd.env)
                     .env("ELECTRON_RUN_AS_NODE", "1")
+                    .env(HANDSHAKE_ENV, "1")
+                    .env(BOOTSTRAP_TOKEN_ENV, &token)
+                    .stdin(Stdio::null())
                     .stdout(Stdio::piped())
                     .stderr(Stdio::piped());
+                process_tree::isolate(&mut c);
                 if let Some(ref cwd) = cwd {
                     c.current_dir(cwd);
                 }
@@ -402,138 +833,403 @@ impl CliProcessManager {
         };
 
         let pid = child.id();
-        log_line(&format!("spawned pid={pid}"));
+        info!(pid; "spawned cli");
         {
+            let mut history = self.history.lock();
+            history.set_startup_pid(pid);
+            history.mark(StartupPhase::Spawned, now_millis());
+        }
+        {
+            // `stop` bumps the run before it clears the pid under this lock, so checking here
+            // keeps a superseded spawn from showing up in the status.
             let mut locked = status.lock();
-            locked.pid = Some(pid);
+            if self.run_id.load(Ordering::SeqCst) == run_id {
+                locked.pid = Some(pid);
+                Self::emit_status(&host, &locked);
+            }
         }
-        Self::emit_status(&app, &status.lock());
+
+        let tree = ProcessTree::attach(&child);
+        let stdout = child.stdout.take().map(BufReader::new);
+        let stderr = child.stderr.take().map(BufReader::new);
+
+        let manager = self.clone();
+        let spawned_at = Instant::now();
+        let exit_host = host.clone();
+        let handle = ChildHandle::spawn_owner(child, generation, move |code| {
+            // A newer spawn or `stop` has already cleaned up after this one.
+            let current = manager
+                .child
+                .lock()
+                .as_ref()
+                .is_some_and(|child| child.generation() == generation);
+            if current {
+                // Children of a crashed server would otherwise keep holding ports.
+                manager.release_tree(pid);
+                manager.clear_server_lock(pid);
+            }
+            manager.handle_exit(&exit_host, dev, run_id, code, spawned_at.elapsed());
+        });
 
         {
-            let mut holder = child_holder.lock();
-            *holder = Some(child);
+            // `stop` bumps the run before it looks at the tree, so under this lock either it
+            // finds this server or this sees the bump. Checking anywhere else would let a
+            // superseded spawn replace the tree and handle of a newer one.
+            let mut current_tree = self.tree.lock();
+            if self.run_id.load(Ordering::SeqCst) != run_id {
+                drop(current_tree);
+                debug!(pid; "spawn superseded by stop/start; killing it");
+                tree.kill();
+                handle.kill();
+                return Ok(());
+            }
+            *current_tree = Some(tree);
+            *self.child.lock() = Some(handle);
         }
+        self.write_server_lock(ServerLock {
+            app_pid: std::process::id(),
+            server_pid: pid,
+            entry: resolution.entry.clone(),
+            started_at: now_millis(),
+            port: None,
+            url: None,
+            server_version: None,
+            capabilities: Vec::new(),
+            session_id: None,
+        });
 
-        let child_clone = child_holder.clone();
-        let status_clone = status.clone();
-        let app_clone = app.clone();
-        let ready_clone = ready.clone();
-        let token_clone = bootstrap_token.clone();
+        // One reader per pipe: draining them in turn lets a chatty stderr fill up and stall
+        // the server while we are still waiting on stdout.
+        if let Some(reader) = stdout {
+            self.spawn_stream_reader(reader, LogStream::Stdout, host.clone());
+        }
+        if let Some(reader) = stderr {
+            self.spawn_stream_reader(reader, LogStream::Stderr, host.clone());
+        }
 
+        self.spawn_startup_watchdog(host.clone(), generation, pid);
+
+        self.spawn_health_monitor(host.clone(), run_id, Some(pid));
+        self.spawn_metrics_monitor(host, run_id, pid);
+
+        Ok(())
+    }
+
+    /// Reports progress until the server spawned as `generation` is ready, and kills it if
+    /// that takes longer than the configured timeout. Exits quietly once a newer spawn or a
+    /// `stop` has bumped the generation.
+    fn spawn_startup_watchdog(&self, host: Host, generation: u64, pid: u32) {
+        let config = load_desktop_config().startup;
+        let timeout = config.timeout();
+        let manager = self.clone();
         thread::spawn(move || {
-            let stdout = child_clone
-                .lock()
-                .as_mut()
-                .and_then(|c| c.stdout.take())
-                .map(BufReader::new);
-            let stderr = child_clone
-                .lock()
-                .as_mut()
-                .and_then(|c| c.stderr.take())
-                .map(BufReader::new);
-
-            if let Some(reader) = stdout {
-                Self::process_stream(
-                    reader,
-                    "stdout",
-                    &app_clone,
-                    &status_clone,
-                    &ready_clone,
-                    &token_clone,
+            let started = Instant::now();
+            let step = Duration::from_millis(config.progress_interval_ms.max(100));
+            let superseded = || manager.spawn_generation.load(Ordering::SeqCst) != generation;
+
+            loop {
+                thread::sleep(step);
+                if superseded() || manager.ready.load(Ordering::SeqCst) {
+                    return;
+                }
+                let elapsed = started.elapsed();
+                if timeout.is_some_and(|timeout| elapsed >= timeout) {
+                    break;
+                }
+                host.emit(
+                    "cli:startup-progress",
+                    json!({
+                        "pid": pid,
+                        "elapsedMs": elapsed.as_millis() as u64,
+                        "timeoutMs": timeout.map(|timeout| timeout.as_millis() as u64),
+                    }),
                 );
             }
-            if let Some(reader) = stderr {
-                Self::process_stream(
-                    reader,
-                    "stderr",
-                    &app_clone,
-                    &status_clone,
-                    &ready_clone,
-                    &token_clone,
-                );
+
+            // Spawning a new child updates the pid under this lock, so checking both here
+            // keeps the kill from reaching a newer process.
+            let mut locked = manager.status.lock();
+            if superseded() || locked.state != CliState::Starting || locked.pid != Some(pid) {
+                return;
+            }
+            let message = format!(
+                "CLI did not start within {}s",
+                timeout.unwrap_or_default().as_secs()
+            );
+            // A supervised restart that hangs is another failed attempt; `handle_exit` decides
+            // whether to try again once the kill lands.
+            let supervised = locked.restart_attempt.is_some();
+            manager.set_state(
+                &mut locked,
+                if supervised {
+                    CliState::Restarting
+                } else {
+                    CliState::Error
+                },
+                TransitionReason::StartupTimeout,
+            );
+            locked.error = Some(message.clone());
+            error!(pid, supervised; "timeout waiting for CLI readiness");
+            manager.kill_tree(pid);
+            if !supervised {
+                host.emit("cli:error", json!({ "message": message }));
             }
+            Self::emit_status(&host, &locked);
         });
+    }
 
-        let app_clone = app.clone();
-        let status_clone = status.clone();
-        let ready_clone = ready.clone();
-        let child_holder_clone = child_holder.clone();
+    /// Probes the server until `run_id` changes. `pid` is `None` for remote servers, which
+    /// are only reported on and never killed.
+    fn spawn_health_monitor(&self, host: Host, run_id: u64, pid: Option<u32>) {
+        let config = load_desktop_config().health;
+        if !config.enabled {
+            return;
+        }
+
+        let manager = self.clone();
         thread::spawn(move || {
-            let timeout = Duration::from_secs(60);
-            thread::sleep(timeout);
-            if ready_clone.load(Ordering::SeqCst) {
-                return;
-            }
-            let mut locked = status_clone.lock();
-            locked.state = CliState::Error;
-            locked.error = Some("CLI did not start in time".to_string());
-            log_line("timeout waiting for CLI readiness");
-            if let Some(child) = child_holder_clone.lock().as_mut() {
-                let _ = child.kill();
+            let interval = Duration::from_secs(config.interval_secs.max(1));
+            let timeout = Duration::from_millis(config.timeout_ms);
+            let path = config.path.clone();
+            let mut tracker = HealthTracker::new(config);
+
+            loop {
+                thread::sleep(interval);
+                if manager.run_id.load(Ordering::SeqCst) != run_id {
+                    return;
+                }
+
+                let (state, url, current_pid) = {
+                    let locked = manager.status.lock();
+                    (locked.state.clone(), locked.url.clone(), locked.pid)
+                };
+                if current_pid != pid {
+                    return;
+                }
+                if !state.is_running() {
+                    if state == CliState::Starting {
+                        continue;
+                    }
+                    return;
+                }
+                let Some(base_url) = url else {
+                    continue;
+                };
+
+                // Auth rejections still prove the event loop is serving requests.
+                let healthy = match probe(&base_url, &path, timeout) {
+                    Ok(code) => code < 500,
+                    Err(err) => {
+                        debug!(error:% = err; "health probe failed");
+                        false
+                    }
+                };
+                let verdict = tracker.record(healthy);
+                let next_state = match verdict {
+                    HealthVerdict::Healthy => CliState::Ready,
+                    HealthVerdict::Degraded => CliState::Degraded,
+                    HealthVerdict::Unresponsive | HealthVerdict::Restart => CliState::Unresponsive,
+                };
+
+                {
+                    let mut locked = manager.status.lock();
+                    if locked.pid != pid || !locked.state.is_running() {
+                        return;
+                    }
+                    if locked.state != next_state {
+                        info!(
+                            from:? = locked.state,
+                            to:? = next_state,
+                            misses = tracker.misses();
+                            "health state changed"
+                        );
+                        manager.set_state(
+                            &mut locked,
+                            next_state,
+                            TransitionReason::HealthCheck {
+                                missed_probes: tracker.misses(),
+                            },
+                        );
+                        locked.error = if tracker.misses() > 0 {
+                            Some(format!("Server missed {} health checks", tracker.misses()))
+                        } else {
+                            None
+                        };
+                        Self::emit_status(&host, &locked);
+                    }
+                }
+
+                if let (HealthVerdict::Restart, Some(pid)) = (verdict, pid) {
+                    warn!(pid; "server unresponsive; killing it so the supervisor restarts it");
+                    manager.kill_tree(pid);
+                    return;
+                }
             }
-            let _ = app_clone.emit("cli:error", json!({"message": "CLI did not start in time"}));
-            Self::emit_status(&app_clone, &locked);
         });
+    }
+
+    /// Samples the server tree's CPU and memory until `run_id` changes or the server exits,
+    /// acting on the thresholds from `desktop.json`.
+    fn spawn_metrics_monitor(&self, host: Host, run_id: u64, pid: u32) {
+        let config = load_desktop_config().metrics;
+        if !config.enabled {
+            return;
+        }
 
-        let status_clone = status.clone();
-        let app_clone = app.clone();
+        let manager = self.clone();
         thread::spawn(move || {
-            let code = {
-                let mut guard = child_holder.lock();
-                if let Some(child) = guard.as_mut() {
-                    child.wait().ok()
-                } else {
-                    None
+            let interval = Duration::from_secs(config.interval_secs.max(1));
+            let mut sampler = MetricsSampler::default();
+            let mut thresholds = ThresholdTracker::new(config);
+
+            loop {
+                if manager.run_id.load(Ordering::SeqCst) != run_id
+                    || manager.status.lock().pid != Some(pid)
+                {
+                    return;
                 }
-            };
+                let Some(sample) = sampler.sample(pid, now_millis()) else {
+                    return;
+                };
+                manager.metrics.lock().push(sample.clone());
+                host.emit("cli:metrics", &sample);
+
+                for alert in thresholds.record(&sample) {
+                    warn!(
+                        pid,
+                        resource:? = alert.resource,
+                        action:? = alert.action,
+                        value = alert.value,
+                        threshold = alert.threshold;
+                        "server exceeded resource threshold"
+                    );
+                    host.emit("cli:metrics-alert", &alert);
+                    if alert.action == AlertAction::Restart {
+                        manager.kill_tree(pid);
+                        return;
+                    }
+                }
+                thread::sleep(interval);
+            }
+        });
+    }
 
-            let mut locked = status_clone.lock();
-            let failed = locked.state != CliState::Ready;
-            let err_msg = if failed {
-                Some(match code {
+    pub fn metrics(&self, limit: Option<usize>) -> Vec<ResourceSample> {
+        self.metrics.lock().recent(limit)
+    }
+
+    fn handle_exit(
+        &self,
+        host: &Host,
+        dev: bool,
+        run_id: u64,
+        code: Option<ExitStatus>,
+        uptime: Duration,
+    ) {
+        if self.run_id.load(Ordering::SeqCst) != run_id {
+            info!("cli process exited after stop was requested");
+            return;
+        }
+
+        let mut locked = self.status.lock();
+        let supervised = locked.state.is_running() || locked.restart_attempt.is_some();
+
+        if !supervised {
+            if locked.state == CliState::Error {
+                // A rejected handshake or startup timeout killed it and has already reported.
+                debug!(code:? = code; "cli process exited after failing to start");
+                return;
+            }
+            self.set_state(&mut locked, CliState::Error, TransitionReason::exited(code));
+            if locked.error.is_none() {
+                locked.error = Some(match code {
                     Some(status) => format!("CLI exited early: {status}"),
                     None => "CLI exited early".to_string(),
-                })
-            } else {
-                None
-            };
+                });
+            }
+            error!(error:? = locked.error; "cli process exited before ready");
+            host.emit(
+                "cli:error",
+                json!({"message": locked.error.clone().unwrap_or_default()}),
+            );
+            Self::emit_status(host, &locked);
+            return;
+        }
 
-            if failed {
-                locked.state = CliState::Error;
-                if locked.error.is_none() {
-                    locked.error = err_msg.clone();
+        let exit_message = match (&locked.state, &locked.error, code) {
+            // The startup watchdog got here first and already said why.
+            (CliState::Restarting, Some(error), _) => error.clone(),
+            (_, _, Some(status)) => format!("CLI exited unexpectedly: {status}"),
+            (_, _, None) => "CLI exited unexpectedly".to_string(),
+        };
+
+        // The transition records the pid that exited, so the endpoint is cleared after it.
+        match self.supervisor.lock().on_exit(uptime) {
+            RestartDecision::Restart { attempt, delay } => {
+                self.set_state(
+                    &mut locked,
+                    CliState::Restarting,
+                    TransitionReason::exited(code),
+                );
+                Self::clear_endpoint(&mut locked);
+                locked.error = Some(exit_message);
+                locked.restart_attempt = Some(attempt);
+                locked.next_retry_at = Some(now_millis() + delay.as_millis() as u64);
+                warn!(
+                    uptime_secs = uptime.as_secs(),
+                    attempt,
+                    delay_ms = delay.as_millis() as u64;
+                    "cli process exited; restarting"
+                );
+                Self::emit_status(host, &locked);
+                drop(locked);
+
+                thread::sleep(delay);
+                if self.run_id.load(Ordering::SeqCst) != run_id {
+                    debug!("pending restart superseded by stop/start");
+                    return;
                 }
-                log_line(&format!(
-                    "cli process exited before ready: {:?}",
-                    locked.error
-                ));
-                let _ = app_clone.emit(
+                self.launch(
+                    host.clone(),
+                    dev,
+                    run_id,
+                    TransitionReason::SupervisorRestart { attempt },
+                );
+            }
+            RestartDecision::GiveUp { reason } => {
+                self.set_state(
+                    &mut locked,
+                    CliState::Error,
+                    TransitionReason::SupervisorGaveUp {
+                        reason: reason.to_string(),
+                    },
+                );
+                Self::clear_endpoint(&mut locked);
+                locked.error = Some(format!("{exit_message}. {reason}"));
+                locked.next_retry_at = None;
+                error!(reason:% = reason; "supervisor giving up");
+                host.emit(
                     "cli:error",
                     json!({"message": locked.error.clone().unwrap_or_default()}),
                 );
-            } else {
-                locked.state = CliState::Stopped;
-                log_line("cli process stopped cleanly");
+                Self::emit_status(host, &locked);
             }
-
-            Self::emit_status(&app_clone, &locked);
-        });
-
-        Ok(())
+        }
     }
 
-    fn process_stream<R: BufRead>(
-        mut reader: R,
-        stream: &str,
-        app: &AppHandle,
-        status: &Arc<Mutex<CliStatus>>,
-        ready: &Arc<AtomicBool>,
-        bootstrap_token: &Arc<Mutex<Option<String>>>,
+    fn spawn_stream_reader<R: BufRead + Send + 'static>(
+        &self,
+        reader: R,
+        stream: LogStream,
+        host: Host,
     ) {
+        let manager = self.clone();
+        thread::spawn(move || manager.process_stream(reader, stream, &host));
+    }
+
+    fn process_stream<R: BufRead>(&self, mut reader: R, stream: LogStream, host: &Host) {
         let mut buffer = String::new();
-        let port_regex = Regex::new(r"AgroForge Server is ready at http://[^:]+:(\d+)").ok();
-        let http_regex = Regex::new(r":(\d{2,5})(?!.*:\d)").ok();
-        let token_prefix = "AGROFORGE_BOOTSTRAP_TOKEN:";
+        let mut handshake_rejected = false;
 
         loop {
             buffer.clear();
@@ -541,56 +1237,57 @@ impl CliProcessManager {
                 Ok(0) => break,
                 Ok(_) => {
                     let line = buffer.trim_end();
-                    if !line.is_empty() {
-                        if line.starts_with(token_prefix) {
-                            let token = line.trim_start_matches(token_prefix).trim();
-                            if !token.is_empty() {
-                                let mut guard = bootstrap_token.lock();
-                                if guard.is_none() {
-                                    *guard = Some(token.to_string());
-                                }
-                            }
-                            continue;
+                    if line.is_empty() {
+                        continue;
+                    }
+                    // Never let a token line reach the logs. A server that predates
+                    // BOOTSTRAP_TOKEN_ENV ignores ours and announces its own instead.
+                    if let Some(token) = line.strip_prefix(BOOTSTRAP_TOKEN_PREFIX) {
+                        let token = token.trim();
+                        if !token.is_empty() {
+                            *self.bootstrap_token.lock() = Some(token.to_string());
                         }
+                        continue;
+                    }
 
-                        log_line(&format!("[cli][{}] {}", stream, line));
+                    self.history
+                        .lock()
+                        .mark(StartupPhase::FirstOutput, now_millis());
+                    let redacted = redact_url_fragments(line);
+                    let entry = self.logs.lock().push(stream, &redacted, now_millis());
+                    log::log!(
+                        target: SERVER_TARGET,
+                        entry.level.into(),
+                        stream = stream.as_str();
+                        "{redacted}"
+                    );
+                    host.emit("cli:log", entry);
+
+                    if handshake_rejected || self.ready.load(Ordering::SeqCst) {
+                        continue;
+                    }
 
-                        if ready.load(Ordering::SeqCst) {
-                            continue;
+                    match parse_ready_line(line) {
+                        Some(Ok(ReadySignal::Handshake(handshake))) => {
+                            info!(
+                                version = handshake.protocol_version,
+                                pid = handshake.pid,
+                                host = handshake.host.as_str(),
+                                port = handshake.port,
+                                capabilities:? = handshake.capabilities;
+                                "handshake received"
+                            );
+                            self.mark_ready(host, handshake.port, Some(&handshake));
                         }
-
-                        if let Some(port) = port_regex
-                            .as_ref()
-                            .and_then(|re| re.captures(line).and_then(|c| c.get(1)))
-                            .and_then(|m| m.as_str().parse::<u16>().ok())
-                        {
-                            Self::mark_ready(app, status, ready, bootstrap_token, port);
-                            continue;
+                        Some(Ok(ReadySignal::Legacy { port })) => {
+                            info!(port; "readiness detected from legacy log output");
+                            self.mark_ready(host, port, None);
                         }
-
-                        if line.to_lowercase().contains("http server listening") {
-                            if let Some(port) = http_regex
-                                .as_ref()
-                                .and_then(|re| re.captures(line).and_then(|c| c.get(1)))
-                                .and_then(|m| m.as_str().parse::<u16>().ok())
-                            {
-                                Self::mark_ready(app, status, ready, bootstrap_token, port);
-                                continue;
-                            }
-
-                            if let Ok(value) = serde_json::from_str::<serde_json::Value>(line) {
-                                if let Some(port) = value.get("port").and_then(|p| p.as_u64()) {
-                                    Self::mark_ready(
-                                        app,
-                                        status,
-                                        ready,
-                                        bootstrap_token,
-                                        port as u16,
-                                    );
-                                    continue;
-                                }
-                            }
+                        Some(Err(err)) => {
+                            handshake_rejected = true;
+                            self.reject_handshake(host, &err);
                         }
+                        None => {}
                     }
                 }
                 Err(_) => break,
@@ -598,52 +1295,195 @@ impl CliProcessManager {
         }
     }
 
-    fn mark_ready(
-        app: &AppHandle,
-        status: &Arc<Mutex<CliStatus>>,
-        ready: &Arc<AtomicBool>,
-        bootstrap_token: &Arc<Mutex<Option<String>>>,
-        port: u16,
-    ) {
-        ready.store(true, Ordering::SeqCst);
-        let base_url = format!("http://127.0.0.1:{port}");
-        let mut locked = status.lock();
-        locked.port = Some(port);
-        locked.url = Some(base_url.clone());
-        locked.state = CliState::Ready;
-        locked.error = None;
-        log_line(&format!("cli ready on {base_url}"));
-
-        let token = bootstrap_token.lock().take();
-
-        if let Some(token) = token {
-            match exchange_bootstrap_token(&base_url, &token) {
-                Ok(Some(session_id)) => {
-                    if let Err(err) = set_session_cookie(app, &base_url, &session_id) {
-                        log_line(&format!("failed to set session cookie: {err}"));
-                        navigate_main(app, &format!("{base_url}/login"));
-                    } else {
-                        navigate_main(app, &base_url);
-                    }
-                }
+    fn reject_handshake(&self, host: &Host, err: &HandshakeError) {
+        let message = err.to_string();
+        error!(reason:% = message; "handshake rejected");
+        let mut locked = self.status.lock();
+        self.set_state(
+            &mut locked,
+            CliState::Error,
+            TransitionReason::HandshakeRejected {
+                message: message.clone(),
+            },
+        );
+        locked.error = Some(message.clone());
+        // An incompatible server will not get better by restarting it.
+        locked.restart_attempt = None;
+        if let Some(pid) = locked.pid {
+            self.kill_tree(pid);
+        }
+        host.emit("cli:error", json!({ "message": message }));
+        Self::emit_status(host, &locked);
+    }
+
+    fn mark_ready(&self, host: &Host, port: u16, handshake: Option<&ReadyHandshake>) {
+        // Both output streams are read at once; only the first readiness line counts.
+        if self.ready.swap(true, Ordering::SeqCst) {
+            return;
+        }
+        // Handshakes are only accepted with a loopback host, so the fallback is for legacy lines.
+        let base_url = handshake
+            .and_then(|handshake| handshake.base_url().ok())
+            .unwrap_or_else(|| format!("http://127.0.0.1:{port}"));
+        // The token exchange and the webview calls below can take seconds, and
+        // `cli_get_status` runs on the main thread, so the lock is only held to update the status.
+        let (pid, server_version, capabilities) = {
+            let mut locked = self.status.lock();
+            locked.port = Some(port);
+            locked.url = Some(base_url.clone());
+            self.set_state(&mut locked, CliState::Ready, TransitionReason::Ready);
+            self.history.lock().mark(StartupPhase::Ready, now_millis());
+            locked.error = None;
+            locked.restart_attempt = None;
+            locked.next_retry_at = None;
+            if let Some(handshake) = handshake {
+                locked.server_version = handshake.server_version.clone();
+                locked.capabilities = handshake.capabilities.clone();
+            }
+            (
+                locked.pid,
+                locked.server_version.clone(),
+                locked.capabilities.clone(),
+            )
+        };
+        info!(port, url = base_url.as_str(); "cli ready");
+        self.update_server_lock(|lock| {
+            lock.port = Some(port);
+            lock.url = Some(base_url.clone());
+            lock.server_version = server_version;
+            lock.capabilities = capabilities;
+        });
+
+        // `None` when there was no token to exchange, `Some(None)` when the exchange failed.
+        let exchange = self.bootstrap_token.lock().take().map(|token| {
+            let exchanged = exchange_bootstrap_token(&base_url, &token);
+            self.history
+                .lock()
+                .mark(StartupPhase::TokenExchange, now_millis());
+            match exchanged {
+                Ok(Some(session_id)) => Some(session_id),
                 Ok(None) => {
-                    log_line("bootstrap token exchange failed (invalid token)");
-                    navigate_main(app, &format!("{base_url}/login"));
+                    warn!("bootstrap token exchange failed (invalid token)");
+                    None
                 }
                 Err(err) => {
-                    log_line(&format!("bootstrap token exchange failed: {err}"));
-                    navigate_main(app, &format!("{base_url}/login"));
+                    warn!(error:% = err; "bootstrap token exchange failed");
+                    None
                 }
             }
-        } else {
-            navigate_main(app, &base_url);
+        });
+
+        // A `stop` or a newer spawn during the exchange owns the window from here on.
+        let superseded = |status: &CliStatus| status.pid != pid || !status.state.is_running();
+        if superseded(&self.status.lock()) {
+            debug!(port; "server stopped or replaced before it could be opened");
+            return;
+        }
+        let url = match exchange {
+            None => base_url.clone(),
+            Some(Some(session_id)) => {
+                *self.session_id.lock() = Some(session_id.clone());
+                self.update_server_lock(|lock| lock.session_id = Some(session_id.clone()));
+                match set_session_cookie(host, &base_url, &session_id) {
+                    Ok(()) => base_url.clone(),
+                    Err(err) => {
+                        warn!(error:% = err; "failed to set session cookie");
+                        format!("{base_url}/login")
+                    }
+                }
+            }
+            Some(None) => format!("{base_url}/login"),
+        };
+        host.navigate_main(&url);
+        self.history
+            .lock()
+            .mark(StartupPhase::Navigated, now_millis());
+
+        let locked = self.status.lock();
+        if !superseded(&locked) {
+            host.emit("cli:ready", locked.clone());
+            Self::emit_status(host, &locked);
         }
-        let _ = app.emit("cli:ready", locked.clone());
-        Self::emit_status(app, &locked);
     }
 
-    fn emit_status(app: &AppHandle, status: &CliStatus) {
-        let _ = app.emit("cli:status", status.clone());
+    pub fn history(&self) -> HistorySnapshot {
+        self.history.lock().snapshot()
+    }
+
+    /// Changes `status.state` and records why. Setting the current state again is not
+    /// recorded, and transitions the lifecycle does not allow are dropped.
+    fn set_state(&self, status: &mut CliStatus, to: CliState, reason: TransitionReason) {
+        if status.state == to {
+            return;
+        }
+        if !status.state.can_transition_to(&to) {
+            warn!(from:? = status.state, to:? = to, reason:? = reason; "ignoring invalid state transition");
+            return;
+        }
+        debug!(from:? = status.state, to:? = to, reason:? = reason, pid:? = status.pid; "state transition");
+        self.history.lock().record(StatusTransition {
+            timestamp: now_millis(),
+            from: std::mem::replace(&mut status.state, to.clone()),
+            to,
+            reason,
+            pid: status.pid,
+        });
+    }
+
+    /// Kills the server started as `pid` and everything it spawned, unless a newer
+    /// process has replaced it in the meantime.
+    fn kill_tree(&self, pid: u32) {
+        if let Some(tree) = self.tree.lock().as_ref().filter(|tree| tree.pid() == pid) {
+            tree.kill();
+        }
+    }
+
+    /// Kills what is left of the tree started as `pid` and forgets it. Once the server has been
+    /// reaped its pid can be reused, possibly as the leader of an unrelated process group, so
+    /// nothing may signal the group after this.
+    fn release_tree(&self, pid: u32) {
+        let mut tree = self.tree.lock();
+        if tree.as_ref().is_some_and(|tree| tree.pid() == pid) {
+            if let Some(tree) = tree.take() {
+                tree.kill();
+            }
+        }
+    }
+
+    fn clear_endpoint(status: &mut CliStatus) {
+        status.pid = None;
+        status.port = None;
+        status.url = None;
+    }
+
+    fn emit_status(host: &Host, status: &CliStatus) {
+        host.emit("cli:status", status.clone());
+    }
+}
+
+fn request_api_shutdown(base_url: &str, session_id: Option<&str>) -> anyhow::Result<()> {
+    let mut request =
+        HttpRequest::new("POST", &format!("{base_url}/api/server/shutdown"))?.json(&json!({}))?;
+    if let Some(session_id) = session_id {
+        request = request.header("Cookie", &format!("{SESSION_COOKIE_NAME}={session_id}"))?;
+    }
+    let response = HttpClient::new().send(request)?;
+    if !response.is_success() {
+        anyhow::bail!("server answered HTTP {}", response.status);
+    }
+    Ok(())
+}
+
+fn wait_until(timeout: Duration, mut condition: impl FnMut() -> bool) -> bool {
+    let deadline = Instant::now() + timeout;
+    loop {
+        if condition() {
+            return true;
+        }
+        if Instant::now() >= deadline {
+            return false;
+        }
+        thread::sleep(Duration::from_millis(50));
     }
 }
 
@@ -661,6 +1501,8 @@ struct ShellCommand {
 struct DirectCommand {
     program: String,
     args: Vec<String>,
+    /// Applied on top of the app's own environment.
+    env: HashMap<String, String>,
 }
 
 #[derive(Debug)]
@@ -669,27 +1511,39 @@ enum ShellCommandType {
     Direct(DirectCommand),
 }
 
-#[derive(Debug)]
-struct CliEntry {
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CliEntry {
     entry: String,
     runner: Runner,
     runner_path: Option<String>,
     node_binary: String,
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
 enum Runner {
     Node,
     Tsx,
 }
 
 impl CliEntry {
-    fn resolve(app: &AppHandle, dev: bool) -> anyhow::Result<Self> {
-        let node_binary = std::env::var("NODE_BINARY").unwrap_or_else(|_| "node".to_string());
+    pub fn resolve(dev: bool) -> anyhow::Result<Self> {
+        let runtime = node::discover();
+        let Some(node) = runtime.selected else {
+            anyhow::bail!(runtime.error.unwrap_or_default());
+        };
+        info!(
+            path = node.path.as_str(),
+            version:? = node.version,
+            source:? = node.source;
+            "selected node runtime"
+        );
+        let node_binary = node.path;
 
         if dev {
-            if let Some(tsx_path) = resolve_tsx(app) {
-                if let Some(entry) = resolve_dev_entry(app) {
+            if let Some(tsx_path) = resolve_tsx() {
+                if let Some(entry) = resolve_dev_entry() {
                     return Ok(Self {
                         entry,
                         runner: Runner::Tsx,
@@ -700,7 +1554,7 @@ impl CliEntry {
             }
         }
 
-        if let Some(entry) = resolve_dist_entry(app) {
+        if let Some(entry) = resolve_dist_entry() {
             return Ok(Self {
                 entry,
                 runner: Runner::Node,
@@ -714,7 +1568,7 @@ impl CliEntry {
         ))
     }
 
-    fn build_args(&self, dev: bool, host: &str) -> Vec<String> {
+    fn build_args(&self, dev: bool, host: &str, workspace_root: Option<&str>) -> Vec<String> {
         let mut args = vec![
             "serve".to_string(),
             "--host".to_string(),
@@ -723,12 +1577,16 @@ impl CliEntry {
             "0".to_string(),
             "--generate-token".to_string(),
         ];
+        if let Some(root) = workspace_root {
+            args.push("--workspace-root".to_string());
+            args.push(root.to_string());
+        }
         if dev {
             args.push("--ui-dev-server".to_string());
             args.push("http://localhost:3000".to_string());
-            args.push("--log-level".to_string());
-            args.push("debug".to_string());
         }
+        args.push("--log-level".to_string());
+        args.push(logs::level().as_str().to_string());
         args
     }
 
@@ -747,7 +1605,7 @@ impl CliEntry {
     }
 }
 
-fn resolve_tsx(_app: &AppHandle) -> Option<String> {
+fn resolve_tsx() -> Option<String> {
     let candidates = vec![
         std::env::current_dir()
             .ok()
@@ -761,7 +1619,7 @@ fn resolve_tsx(_app: &AppHandle) -> Option<String> {
     first_existing(candidates)
 }
 
-fn resolve_dev_entry(_app: &AppHandle) -> Option<String> {
+fn resolve_dev_entry() -> Option<String> {
     let candidates = vec![
         std::env::current_dir()
             .ok()
@@ -774,7 +1632,7 @@ fn resolve_dev_entry(_app: &AppHandle) -> Option<String> {
     first_existing(candidates)
 }
 
-fn resolve_dist_entry(_app: &AppHandle) -> Option<String> {
+fn resolve_dist_entry() -> Option<String> {
     let base = workspace_root();
     let mut candidates: Vec<Option<PathBuf>> = vec![
         base.as_ref().map(|p| p.join("packages/server/dist/bin.js")),
@@ -819,57 +1677,16 @@ fn build_shell_command_string(
     entry: &CliEntry,
     cli_args: &[String],
 ) -> anyhow::Result<ShellCommand> {
-    let shell = default_shell();
-    let mut quoted: Vec<String> = Vec::new();
-    quoted.push(shell_escape(&entry.node_binary));
-    for arg in entry.runner_args(cli_args) {
-        quoted.push(shell_escape(&arg));
-    }
-    let command = format!("ELECTRON_RUN_AS_NODE=1 exec {}", quoted.join(" "));
-    let args = build_shell_args(&shell, &command);
-    log_line(&format!("user shell command: {} {:?}", shell, args));
-    Ok(ShellCommand { shell, args })
-}
-
-fn default_shell() -> String {
-    if let Ok(shell) = std::env::var("SHELL") {
-        if !shell.trim().is_empty() {
-            return shell;
-        }
-    }
-    if cfg!(target_os = "macos") {
-        "/bin/zsh".to_string()
-    } else {
-        "/bin/bash".to_string()
-    }
-}
-
-fn shell_escape(input: &str) -> String {
-    if input.is_empty() {
-        "''".to_string()
-    } else if !input
-        .chars()
-        .any(|c| matches!(c, ' ' | '"' | '\'' | '$' | '`' | '!'))
-    {
-        input.to_string()
-    } else {
-        let escaped = input.replace('\'', "'\\''");
-        format!("'{}'", escaped)
-    }
-}
-
-fn build_shell_args(shell: &str, command: &str) -> Vec<String> {
-    let shell_name = std::path::Path::new(shell)
-        .file_name()
-        .and_then(OsStr::to_str)
-        .unwrap_or("")
-        .to_lowercase();
-
-    if shell_name.contains("zsh") {
-        vec!["-l".into(), "-i".into(), "-c".into(), command.into()]
-    } else {
-        vec!["-l".into(), "-c".into(), command.into()]
-    }
+    let shell = UserShell::detect();
+    let mut words = vec![entry.node_binary.clone()];
+    words.extend(entry.runner_args(cli_args));
+    let command = shell.kind.exec_command(&words);
+    let args = shell.args(&command);
+    debug!(shell = shell.path.as_str(), kind:? = shell.kind, args:? = args; "user shell command");
+    Ok(ShellCommand {
+        shell: shell.path.clone(),
+        args,
+    })
 }
 
 fn first_existing(paths: Vec<Option<PathBuf>>) -> Option<String> {
@@ -877,7 +1694,7 @@ fn first_existing(paths: Vec<Option<PathBuf>>) -> Option<String> {
         .into_iter()
         .flatten()
         .find(|p| p.exists())
-        .map(|p| normalize_path(p))
+        .map(normalize_path)
 }
 
 fn normalize_path(path: PathBuf) -> String {
@@ -887,3 +1704,620 @@ fn normalize_path(path: PathBuf) -> String {
         path.to_string_lossy().to_string()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{self, FakeServer, HostCall, MockHost};
+    use std::fs;
+    use std::io::Cursor;
+
+    const WAIT: Duration = Duration::from_secs(15);
+
+    /// A manager that runs `server`'s script with `/bin/sh` instead of the real server.
+    fn manager_for(server: &FakeServer) -> CliProcessManager {
+        testing::setup();
+        let script = server.script();
+        CliProcessManager::with_entry_resolver(move |_| {
+            Ok(CliEntry {
+                entry: script.clone(),
+                runner: Runner::Node,
+                runner_path: None,
+                node_binary: "/bin/sh".to_string(),
+            })
+        })
+    }
+
+    fn wait_for_state(manager: &CliProcessManager, state: CliState) -> bool {
+        wait_until(WAIT, || manager.status().state == state)
+    }
+
+    fn wait_for_event(host: &MockHost, event: &str) -> bool {
+        wait_until(WAIT, || !host.emitted(event).is_empty())
+    }
+
+    #[test]
+    fn handshake_marks_server_ready() {
+        let server = FakeServer::start(&[
+            FakeServer::SHARE_TOKEN,
+            FakeServer::HANDSHAKE,
+            FakeServer::SERVE,
+        ]);
+        let manager = manager_for(&server);
+        let host = MockHost::new();
+
+        manager.start(host.clone(), false).unwrap();
+        assert!(wait_for_event(&host, "cli:ready"));
+
+        let status = manager.status();
+        assert_eq!(status.state, CliState::Ready);
+        assert_eq!(status.port, Some(server.port()));
+        assert_eq!(status.url.as_deref(), Some(server.base_url().as_str()));
+        assert_eq!(status.server_version.as_deref(), Some("0.0.0-test"));
+        assert_eq!(status.capabilities, vec![SHUTDOWN_CAPABILITY.to_string()]);
+        let reasons: Vec<_> = manager
+            .history()
+            .transitions
+            .into_iter()
+            .map(|transition| (transition.from, transition.to))
+            .collect();
+        assert_eq!(
+            reasons,
+            vec![
+                (CliState::Stopped, CliState::Starting),
+                (CliState::Starting, CliState::Ready),
+            ]
+        );
+
+        manager.stop().unwrap();
+    }
+
+    #[test]
+    fn legacy_ready_line_marks_server_ready() {
+        let server = FakeServer::start(&[
+            r#"echo "AgroForge Server is ready at http://127.0.0.1:$PORT""#,
+            FakeServer::SERVE,
+        ]);
+        let manager = manager_for(&server);
+        let host = MockHost::new();
+
+        manager.start(host.clone(), false).unwrap();
+        assert!(wait_for_event(&host, "cli:ready"));
+        let status = manager.status();
+        assert_eq!(status.port, Some(server.port()));
+        assert!(status.capabilities.is_empty());
+
+        manager.stop().unwrap();
+    }
+
+    #[test]
+    fn exchanged_token_sets_session_cookie() {
+        let server = FakeServer::start(&[
+            FakeServer::SHARE_TOKEN,
+            FakeServer::HANDSHAKE,
+            FakeServer::SERVE,
+        ]);
+        let manager = manager_for(&server);
+        let host = MockHost::new();
+
+        manager.start(host.clone(), false).unwrap();
+        assert!(wait_for_event(&host, "cli:ready"));
+
+        let exchanges = server.requests("/api/auth/token");
+        assert_eq!(exchanges.len(), 1);
+        assert_eq!(exchanges[0].method, "POST");
+        assert!(exchanges[0].body.contains("\"token\""));
+        assert_eq!(
+            host.cookies(),
+            vec![(
+                SESSION_COOKIE_NAME.to_string(),
+                FakeServer::SESSION.to_string()
+            )]
+        );
+        assert_eq!(host.navigations(), vec![server.base_url()]);
+
+        // The session authenticates the shutdown request.
+        manager.stop().unwrap();
+        let shutdowns = server.requests("/api/server/shutdown");
+        assert_eq!(shutdowns.len(), 1);
+        assert_eq!(manager.status().state, CliState::Stopped);
+    }
+
+    #[test]
+    fn rejected_token_falls_back_to_login_page() {
+        // Without SHARE_TOKEN the fake server accepts no token at all.
+        let server = FakeServer::start(&[FakeServer::HANDSHAKE, FakeServer::SERVE]);
+        let manager = manager_for(&server);
+        let host = MockHost::new();
+
+        manager.start(host.clone(), false).unwrap();
+        assert!(wait_for_event(&host, "cli:ready"));
+
+        assert_eq!(server.requests("/api/auth/token").len(), 1);
+        assert!(host.cookies().is_empty());
+        assert_eq!(
+            host.navigations(),
+            vec![format!("{}/login", server.base_url())]
+        );
+        assert_eq!(manager.status().state, CliState::Ready);
+
+        manager.stop().unwrap();
+    }
+
+    #[test]
+    fn early_exit_moves_to_error() {
+        let server = FakeServer::start(&["echo booting", "exit 3"]);
+        let manager = manager_for(&server);
+        let host = MockHost::new();
+
+        manager.start(host.clone(), false).unwrap();
+        assert!(wait_for_state(&manager, CliState::Error));
+        assert!(wait_for_event(&host, "cli:error"));
+
+        let status = manager.status();
+        assert!(status.error.unwrap().contains("exited early"));
+        let last = manager.history().transitions.pop().unwrap();
+        assert_eq!(last.to, CliState::Error);
+        assert!(matches!(
+            last.reason,
+            TransitionReason::Exited { code: Some(3), .. }
+        ));
+        assert!(host.navigations().is_empty());
+    }
+
+    #[test]
+    fn unsupported_handshake_is_rejected_and_killed() {
+        let server = FakeServer::start(&[
+            r#"echo "{\"handshake\":\"agroforge\",\"protocolVersion\":99,\"host\":\"127.0.0.1\",\"port\":$PORT,\"pid\":$$}""#,
+            FakeServer::SERVE,
+        ]);
+        let manager = manager_for(&server);
+        let host = MockHost::new();
+
+        manager.start(host.clone(), false).unwrap();
+        assert!(wait_for_state(&manager, CliState::Error));
+
+        let status = manager.status();
+        assert!(status.error.unwrap().contains("v99"));
+        let pid = status.pid.unwrap();
+        assert!(wait_until(WAIT, || !is_alive(pid)));
+        // Give the exit a moment to reach `handle_exit`, which must not report it again.
+        thread::sleep(Duration::from_millis(300));
+        assert!(matches!(
+            manager.history().transitions.last().unwrap().reason,
+            TransitionReason::HandshakeRejected { .. }
+        ));
+        assert_eq!(host.emitted("cli:error").len(), 1);
+        let error_statuses = host
+            .emitted("cli:status")
+            .into_iter()
+            .filter(|status| status["state"] == "error")
+            .count();
+        assert_eq!(error_statuses, 1);
+        assert!(host.navigations().is_empty());
+        assert!(server.requests("/api/auth/token").is_empty());
+    }
+
+    #[test]
+    fn handshake_from_a_non_loopback_host_is_rejected() {
+        let server = FakeServer::start(&[
+            r#"echo "{\"handshake\":\"agroforge\",\"protocolVersion\":1,\"host\":\"192.168.1.5\",\"port\":$PORT,\"pid\":$$}""#,
+            FakeServer::SERVE,
+        ]);
+        let manager = manager_for(&server);
+        let host = MockHost::new();
+
+        manager.start(host.clone(), false).unwrap();
+        assert!(wait_for_state(&manager, CliState::Error));
+        assert!(manager.status().error.unwrap().contains("192.168.1.5"));
+        assert!(host.navigations().is_empty());
+        assert!(server.requests("/api/auth/token").is_empty());
+    }
+
+    #[test]
+    fn lifecycle_only_allows_forward_moves() {
+        use CliState::*;
+        let all = [
+            Starting,
+            Ready,
+            Degraded,
+            Unresponsive,
+            Restarting,
+            Stopping,
+            Error,
+            Stopped,
+        ];
+        for state in &all {
+            assert!(state.can_transition_to(&Stopped), "{state:?} -> Stopped");
+        }
+        assert!(Stopped.can_transition_to(&Starting));
+        assert!(Error.can_transition_to(&Starting));
+        assert!(Restarting.can_transition_to(&Starting));
+        assert!(Starting.can_transition_to(&Ready));
+        // Adopting an orphan.
+        assert!(Stopped.can_transition_to(&Ready));
+        assert!(Ready.can_transition_to(&Degraded));
+        assert!(Degraded.can_transition_to(&Unresponsive));
+        assert!(Unresponsive.can_transition_to(&Ready));
+        assert!(Unresponsive.can_transition_to(&Restarting));
+
+        assert!(!Ready.can_transition_to(&Starting));
+        assert!(!Stopping.can_transition_to(&Ready));
+        assert!(!Stopping.can_transition_to(&Error));
+        assert!(!Error.can_transition_to(&Ready));
+        assert!(!Stopped.can_transition_to(&Stopping));
+        assert!(!Stopped.can_transition_to(&Error));
+        assert!(!Starting.can_transition_to(&Degraded));
+        assert!(!Restarting.can_transition_to(&Ready));
+    }
+
+    #[test]
+    fn status_stays_readable_during_token_exchange() {
+        let server = FakeServer::start(&[
+            FakeServer::SLOW_AUTH,
+            FakeServer::SHARE_TOKEN,
+            FakeServer::HANDSHAKE,
+            FakeServer::SERVE,
+        ]);
+        let manager = manager_for(&server);
+        let host = MockHost::new();
+
+        manager.start(host.clone(), false).unwrap();
+        assert!(wait_until(WAIT, || !server
+            .requests("/api/auth/token")
+            .is_empty()));
+        assert!(host.emitted("cli:ready").is_empty());
+        let started = Instant::now();
+        assert_eq!(manager.status().state, CliState::Ready);
+        assert!(started.elapsed() < Duration::from_millis(500));
+
+        assert!(wait_for_event(&host, "cli:ready"));
+        assert_eq!(host.navigations(), vec![server.base_url()]);
+        manager.stop().unwrap();
+    }
+
+    #[test]
+    fn stop_during_startup_never_reports_ready() {
+        let server = FakeServer::start(&[
+            FakeServer::RECORD_PID,
+            "sleep 0.5",
+            FakeServer::HANDSHAKE,
+            FakeServer::SERVE,
+        ]);
+        let manager = manager_for(&server);
+        let host = MockHost::new();
+
+        manager.start(host.clone(), false).unwrap();
+        assert!(wait_until(WAIT, || manager.status().pid.is_some()));
+        let pid = manager.status().pid.unwrap();
+        manager.stop().unwrap();
+
+        assert!(!is_alive(pid));
+        thread::sleep(Duration::from_secs(1));
+        assert_eq!(manager.status().state, CliState::Stopped);
+        assert!(host.emitted("cli:ready").is_empty());
+        assert!(host.navigations().is_empty());
+    }
+
+    #[test]
+    fn stop_racing_the_spawn_leaves_nothing_running() {
+        let server = FakeServer::start(&[
+            FakeServer::RECORD_PID,
+            FakeServer::HANDSHAKE,
+            FakeServer::SERVE,
+        ]);
+        let manager = manager_for(&server);
+        let host = MockHost::new();
+
+        // The spawn happens on a thread of its own, so these stops land before, during and
+        // after it.
+        for _ in 0..5 {
+            manager.start(host.clone(), false).unwrap();
+            manager.stop().unwrap();
+        }
+        thread::sleep(Duration::from_secs(1));
+
+        let status = manager.status();
+        assert_eq!(status.state, CliState::Stopped);
+        assert_eq!(status.pid, None);
+        for pid in server.pids() {
+            assert!(wait_until(WAIT, || !is_alive(pid)), "pid {pid} survived");
+        }
+        let stopped_at = manager.history().transitions.len();
+        thread::sleep(Duration::from_millis(500));
+        assert_eq!(manager.history().transitions.len(), stopped_at);
+    }
+
+    #[test]
+    fn overlapping_starts_leave_only_the_last_server() {
+        let server = FakeServer::start(&[
+            FakeServer::RECORD_PID,
+            FakeServer::SHARE_TOKEN,
+            FakeServer::HANDSHAKE,
+            FakeServer::SERVE,
+        ]);
+        let manager = manager_for(&server);
+        let host = MockHost::new();
+
+        for _ in 0..3 {
+            manager.start(host.clone(), false).unwrap();
+        }
+        assert!(wait_for_event(&host, "cli:ready"));
+        thread::sleep(Duration::from_millis(500));
+
+        let current = manager.status().pid.unwrap();
+        assert_eq!(manager.status().state, CliState::Ready);
+        assert!(is_alive(current));
+        for pid in server.pids().into_iter().filter(|pid| *pid != current) {
+            assert!(wait_until(WAIT, || !is_alive(pid)), "pid {pid} survived");
+        }
+
+        manager.stop().unwrap();
+        assert!(!is_alive(current));
+    }
+
+    #[test]
+    fn restart_replaces_the_running_server() {
+        let server = FakeServer::start(&[
+            FakeServer::SHARE_TOKEN,
+            FakeServer::HANDSHAKE,
+            FakeServer::SERVE,
+        ]);
+        let manager = manager_for(&server);
+        let host = MockHost::new();
+
+        manager.start(host.clone(), false).unwrap();
+        assert!(wait_for_event(&host, "cli:ready"));
+        let first = manager.status().pid.unwrap();
+
+        manager.restart(host.clone(), false).unwrap();
+        assert!(wait_until(WAIT, || host.emitted("cli:ready").len() == 2));
+        let second = manager.status().pid.unwrap();
+        assert_ne!(first, second);
+        assert!(!is_alive(first));
+        assert!(is_alive(second));
+        // The first server was asked to save its sessions rather than killed outright.
+        assert_eq!(server.requests("/api/server/shutdown").len(), 1);
+
+        let states: Vec<_> = manager
+            .history()
+            .transitions
+            .into_iter()
+            .map(|transition| transition.to)
+            .collect();
+        assert_eq!(
+            states,
+            vec![
+                CliState::Starting,
+                CliState::Ready,
+                CliState::Stopping,
+                CliState::Stopped,
+                CliState::Starting,
+                CliState::Ready,
+            ]
+        );
+
+        manager.stop().unwrap();
+        manager.stop().unwrap();
+        assert!(!is_alive(second));
+        assert_eq!(manager.status().state, CliState::Stopped);
+    }
+
+    #[test]
+    fn spawn_failure_moves_to_error() {
+        testing::setup();
+        let manager =
+            CliProcessManager::with_entry_resolver(|_| Err(anyhow::anyhow!("no server build")));
+        let host = MockHost::new();
+
+        manager.start(host.clone(), false).unwrap();
+        assert!(wait_for_state(&manager, CliState::Error));
+        assert!(wait_for_event(&host, "cli:error"));
+        assert_eq!(manager.status().error.as_deref(), Some("no server build"));
+        assert!(matches!(
+            manager.history().transitions.last().unwrap().reason,
+            TransitionReason::SpawnFailed { .. }
+        ));
+    }
+
+    #[test]
+    fn remote_attach_signs_in_and_stop_revokes_the_origin() {
+        let server = FakeServer::start(&[]);
+        let manager = manager_for(&server);
+        let mock = MockHost::new();
+        let host: Host = mock.clone();
+        *manager.host.lock() = Some(host.clone());
+        let remote = RemoteConfig {
+            url: format!("{}/", server.base_url()),
+            username: Some("me".to_string()),
+            password: Some(FakeServer::PASSWORD.to_string()),
+        };
+
+        let run_id = manager.run_id.load(Ordering::SeqCst);
+        manager.attach(host, remote, run_id, TransitionReason::Start);
+        assert!(wait_for_event(&mock, "cli:ready"));
+        assert!(!server.requests("/api/meta").is_empty());
+        assert_eq!(server.requests("/api/auth/login").len(), 1);
+        assert_eq!(
+            mock.cookies(),
+            vec![(
+                SESSION_COOKIE_NAME.to_string(),
+                FakeServer::SESSION.to_string()
+            )]
+        );
+        assert_eq!(mock.navigations(), vec![server.base_url()]);
+
+        manager.stop().unwrap();
+        let origin = server.base_url();
+        let grants: Vec<_> = mock
+            .calls()
+            .into_iter()
+            .filter(|call| matches!(call, HostCall::AllowOrigin(_) | HostCall::RevokeOrigin(_)))
+            .collect();
+        assert_eq!(
+            grants,
+            vec![
+                HostCall::AllowOrigin(origin.clone()),
+                HostCall::RevokeOrigin(origin)
+            ]
+        );
+        assert_eq!(manager.status().url, None);
+    }
+
+    #[test]
+    fn bootstrap_token_never_reaches_the_logs() {
+        testing::setup();
+        let manager = CliProcessManager::new();
+        let mock = MockHost::new();
+        let host: Host = mock.clone();
+        let token = format!("secret-{}", generate_bootstrap_token().unwrap());
+        let output =
+            format!("before the token\n{BOOTSTRAP_TOKEN_PREFIX} {token}\nafter the token\n");
+
+        manager.process_stream(Cursor::new(output), LogStream::Stdout, &host);
+
+        assert_eq!(
+            manager.bootstrap_token.lock().as_deref(),
+            Some(token.as_str())
+        );
+        let lines: Vec<_> = manager
+            .logs(None, None, None)
+            .into_iter()
+            .map(|entry| entry.message)
+            .collect();
+        assert_eq!(lines, vec!["before the token", "after the token"]);
+        let events = mock.emitted("cli:log");
+        assert_eq!(events.len(), 2);
+        assert!(events
+            .iter()
+            .all(|event| !event.to_string().contains(&token)));
+        let log_file = fs::read_to_string(crate::logs::log_file_path().unwrap()).unwrap();
+        assert!(log_file.contains("after the token"));
+        assert!(!log_file.contains(&token));
+    }
+
+    #[test]
+    fn timed_out_restart_counts_as_a_failed_attempt() {
+        let server = FakeServer::start(&[
+            FakeServer::RECORD_PID,
+            // The first run crashes once ready; the supervised restart never gets ready.
+            r#"if [ "$(wc -l < "$DIR/pids")" -gt 1 ]; then sleep 600; fi"#,
+            FakeServer::HANDSHAKE,
+            "sleep 0.3; exit 1",
+        ]);
+        let manager = manager_for(&server);
+        let host = MockHost::new();
+
+        manager.start(host.clone(), false).unwrap();
+        assert!(wait_for_state(&manager, CliState::Error));
+        let moves: Vec<_> = manager
+            .history()
+            .transitions
+            .into_iter()
+            .map(|transition| (transition.from, transition.to, transition.reason))
+            .collect();
+        let timed_out = moves
+            .iter()
+            .find(|(.., reason)| matches!(reason, TransitionReason::StartupTimeout))
+            .unwrap();
+        assert_eq!(
+            (&timed_out.0, &timed_out.1),
+            (&CliState::Starting, &CliState::Restarting)
+        );
+        assert!(matches!(
+            moves.last().unwrap(),
+            (
+                CliState::Restarting,
+                CliState::Error,
+                TransitionReason::SupervisorGaveUp { .. }
+            )
+        ));
+
+        let error = manager.status().error.unwrap();
+        assert!(error.starts_with("CLI did not start within 2s"), "{error}");
+        assert_eq!(host.emitted("cli:error").len(), 1);
+        assert!(host
+            .emitted("cli:status")
+            .iter()
+            .filter(|status| status["state"] == "error")
+            .all(|status| status["nextRetryAt"].is_null()));
+    }
+
+    #[test]
+    fn crash_transitions_record_the_exited_pid() {
+        let server = FakeServer::start(&[
+            FakeServer::RECORD_PID,
+            FakeServer::HANDSHAKE,
+            "sleep 0.3; exit 1",
+        ]);
+        let manager = manager_for(&server);
+        let host = MockHost::new();
+
+        manager.start(host.clone(), false).unwrap();
+        assert!(wait_for_state(&manager, CliState::Error));
+        let pids = server.pids();
+        let transitions = manager.history().transitions;
+        let restarting = transitions
+            .iter()
+            .find(|transition| transition.to == CliState::Restarting)
+            .unwrap();
+        assert!(matches!(restarting.reason, TransitionReason::Exited { .. }));
+        assert_eq!(restarting.pid, Some(pids[0]));
+        let gave_up = transitions.last().unwrap();
+        assert!(matches!(
+            gave_up.reason,
+            TransitionReason::SupervisorGaveUp { .. }
+        ));
+        assert_eq!(gave_up.pid, Some(pids[1]));
+        assert_eq!(manager.status().pid, None);
+        assert_eq!(manager.status().url, None);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn stop_leaves_no_member_of_the_process_group() {
+        let server = FakeServer::start(&[
+            FakeServer::RECORD_PID,
+            FakeServer::FORK_CHILDREN,
+            FakeServer::HANDSHAKE,
+            // Ignores the shutdown API, so stopping has to fall through to the signals.
+            "sleep 600",
+        ]);
+        let manager = manager_for(&server);
+        let host = MockHost::new();
+
+        manager.start(host.clone(), false).unwrap();
+        assert!(wait_for_event(&host, "cli:ready"));
+        let pid = server.pids()[0];
+        assert!(wait_until(WAIT, || testing::group_members(pid).len() >= 4));
+
+        manager.stop().unwrap();
+        assert!(wait_until(Duration::from_secs(2), || {
+            testing::group_members(pid).is_empty()
+        }));
+        assert!(manager.tree.lock().is_none());
+    }
+
+    #[test]
+    fn crashed_server_tree_is_released() {
+        let server = FakeServer::start(&[
+            FakeServer::RECORD_PID,
+            FakeServer::HANDSHAKE,
+            "sleep 0.3; exit 1",
+        ]);
+        let manager = manager_for(&server);
+        let host = MockHost::new();
+
+        manager.start(host.clone(), false).unwrap();
+        // One restart is allowed, so the second crash gives up.
+        assert!(wait_for_state(&manager, CliState::Error));
+        assert_eq!(server.pids().len(), 2);
+        assert!(manager.tree.lock().is_none());
+        assert_eq!(manager.status().pid, None);
+
+        // Nothing is left to signal, so stopping must not touch a reused pid's group.
+        manager.stop().unwrap();
+        assert!(manager.child.lock().is_none());
+        assert_eq!(manager.status().state, CliState::Stopped);
+    }
+}
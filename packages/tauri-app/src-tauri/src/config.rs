@@ -0,0 +1,327 @@
+use crate::logs::LogLevel;
+use dirs::home_dir;
+use serde::{Deserialize, Serialize};
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+const DEFAULT_CONFIG_PATH: &str = "~/.config/codenomad/config.json";
+const DESKTOP_CONFIG_FILENAME: &str = "desktop.json";
+
+#[derive(Debug, Deserialize)]
+struct PreferencesConfig {
+    #[serde(rename = "listeningMode")]
+    listening_mode: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct AppConfig {
+    preferences: Option<PreferencesConfig>,
+}
+
+/// Settings that only the desktop shell reads. They live in `desktop.json` next to
+/// `config.json` because the server rewrites `config.json` and drops unknown keys.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct DesktopConfig {
+    pub supervisor: SupervisorConfig,
+    pub health: HealthConfig,
+    pub shutdown: ShutdownConfig,
+    /// When set, the app attaches to this server instead of spawning its own.
+    pub remote: Option<RemoteConfig>,
+    pub logging: LoggingConfig,
+    pub metrics: MetricsConfig,
+    pub startup: StartupConfig,
+    pub node: NodeConfig,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct SupervisorConfig {
+    pub enabled: bool,
+    pub max_restarts: u32,
+    pub initial_backoff_ms: u64,
+    pub max_backoff_ms: u64,
+    pub crash_loop_threshold: u32,
+    pub crash_loop_window_secs: u64,
+    pub reset_after_secs: u64,
+}
+
+impl Default for SupervisorConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            max_restarts: 5,
+            initial_backoff_ms: 1_000,
+            max_backoff_ms: 30_000,
+            crash_loop_threshold: 3,
+            crash_loop_window_secs: 30,
+            reset_after_secs: 300,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct HealthConfig {
+    pub enabled: bool,
+    pub path: String,
+    pub interval_secs: u64,
+    pub timeout_ms: u64,
+    pub degraded_after: u32,
+    pub unresponsive_after: u32,
+    /// Restart the server once this many probes in a row have failed; `0` disables it.
+    pub restart_after: u32,
+}
+
+impl Default for HealthConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            path: "/api/meta".to_string(),
+            interval_secs: 10,
+            timeout_ms: 3_000,
+            degraded_after: 1,
+            unresponsive_after: 3,
+            restart_after: 0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct ShutdownConfig {
+    /// Ask the server to shut itself down over HTTP before any signal is sent.
+    pub use_api: bool,
+    /// How long the server may spend saving state after the HTTP request.
+    pub api_grace_ms: u64,
+    /// How long to wait after `SIGTERM` before killing the process outright.
+    pub term_grace_ms: u64,
+}
+
+impl Default for ShutdownConfig {
+    fn default() -> Self {
+        Self {
+            use_api: true,
+            api_grace_ms: 10_000,
+            term_grace_ms: 4_000,
+        }
+    }
+}
+
+const STARTUP_TIMEOUT_ENV: &str = "AGROFORGE_STARTUP_TIMEOUT_SECS";
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct StartupConfig {
+    /// How long a spawned server may take to report readiness before it is killed; `0` waits
+    /// forever. `AGROFORGE_STARTUP_TIMEOUT_SECS` takes precedence.
+    pub timeout_secs: u64,
+    /// How often `cli:startup-progress` is emitted while waiting.
+    pub progress_interval_ms: u64,
+}
+
+impl Default for StartupConfig {
+    fn default() -> Self {
+        Self {
+            timeout_secs: 60,
+            progress_interval_ms: 1_000,
+        }
+    }
+}
+
+impl StartupConfig {
+    pub fn timeout(&self) -> Option<Duration> {
+        let secs = env::var(STARTUP_TIMEOUT_ENV)
+            .ok()
+            .and_then(|value| value.trim().parse::<u64>().ok())
+            .unwrap_or(self.timeout_secs);
+        (secs > 0).then(|| Duration::from_secs(secs))
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct NodeConfig {
+    /// Which Node.js runs the server. `NODE_BINARY` takes precedence.
+    pub runtime: NodeRuntime,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum NodeRuntime {
+    /// The runtime shipped in the app bundle if there is one, otherwise the system's.
+    #[default]
+    Auto,
+    Bundled,
+    System,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct MetricsConfig {
+    pub enabled: bool,
+    pub interval_secs: u64,
+    /// Warn once the server tree's resident memory exceeds this many MiB; `0` disables it.
+    pub warn_rss_mb: u64,
+    /// Restart the server once its resident memory exceeds this many MiB; `0` disables it.
+    pub restart_rss_mb: u64,
+    /// Warn once CPU usage exceeds this percentage of one core; `0` disables it.
+    pub warn_cpu_percent: f64,
+    /// Samples in a row a threshold must be exceeded for before it is acted on.
+    pub sustained_samples: u32,
+}
+
+impl Default for MetricsConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            interval_secs: 5,
+            warn_rss_mb: 2048,
+            restart_rss_mb: 0,
+            warn_cpu_percent: 0.0,
+            sustained_samples: 3,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct LoggingConfig {
+    /// Overridden by `AGROFORGE_LOG_LEVEL`; also passed to the server as `--log-level`.
+    pub level: Option<LogLevel>,
+    /// The log file is rotated once it would grow past this size.
+    pub max_file_bytes: u64,
+    /// How many rotated files are kept next to the current one.
+    pub max_files: u32,
+}
+
+impl Default for LoggingConfig {
+    fn default() -> Self {
+        Self {
+            level: None,
+            max_file_bytes: 5 * 1024 * 1024,
+            max_files: 5,
+        }
+    }
+}
+
+const REMOTE_PASSWORD_ENV: &str = "AGROFORGE_REMOTE_PASSWORD";
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct RemoteConfig {
+    pub url: String,
+    pub username: Option<String>,
+    /// Falls back to `AGROFORGE_REMOTE_PASSWORD`, so the password need not be stored on disk.
+    pub password: Option<String>,
+}
+
+impl RemoteConfig {
+    pub fn password(&self) -> Option<String> {
+        self.password
+            .clone()
+            .or_else(|| env::var(REMOTE_PASSWORD_ENV).ok())
+            .filter(|value| !value.is_empty())
+    }
+}
+
+pub fn resolve_config_path() -> PathBuf {
+    let raw = env::var("CLI_CONFIG")
+        .ok()
+        .filter(|value| !value.trim().is_empty())
+        .unwrap_or_else(|| DEFAULT_CONFIG_PATH.to_string());
+    expand_home(&raw)
+}
+
+pub fn resolve_config_dir() -> PathBuf {
+    let path = resolve_config_path();
+    path.parent()
+        .map(|dir| dir.to_path_buf())
+        .unwrap_or_else(|| PathBuf::from("."))
+}
+
+/// Directory shared with the server's pid-tracker. Like the server, it ignores `CLI_CONFIG`.
+pub fn resolve_pid_dir() -> PathBuf {
+    expand_home("~/.config/codenomad/pids")
+}
+
+fn expand_home(path: &str) -> PathBuf {
+    if path.starts_with("~/") {
+        if let Some(home) = home_dir().or_else(|| env::var("HOME").ok().map(PathBuf::from)) {
+            return home.join(path.trim_start_matches("~/"));
+        }
+    }
+    PathBuf::from(path)
+}
+
+pub fn resolve_desktop_config_path() -> PathBuf {
+    resolve_config_dir().join(DESKTOP_CONFIG_FILENAME)
+}
+
+pub fn load_desktop_config() -> DesktopConfig {
+    let path = resolve_desktop_config_path();
+    match fs::read_to_string(&path) {
+        Ok(content) => serde_json::from_str(&content).unwrap_or_else(|err| {
+            log::warn!(path:% = path.display(), error:% = err; "ignoring invalid desktop config");
+            DesktopConfig::default()
+        }),
+        Err(_) => DesktopConfig::default(),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ListeningMode {
+    Local,
+    All,
+}
+
+impl ListeningMode {
+    pub fn host(self) -> &'static str {
+        match self {
+            ListeningMode::Local => "127.0.0.1",
+            ListeningMode::All => "0.0.0.0",
+        }
+    }
+}
+
+pub fn resolve_listening_mode() -> ListeningMode {
+    let path = resolve_config_path();
+    if let Ok(content) = fs::read_to_string(path) {
+        if let Ok(config) = serde_json::from_str::<AppConfig>(&content) {
+            if let Some(mode) = config
+                .preferences
+                .as_ref()
+                .and_then(|prefs| prefs.listening_mode.as_ref())
+            {
+                if mode == "all" {
+                    return ListeningMode::All;
+                }
+            }
+        }
+    }
+    ListeningMode::Local
+}
+
+/// Atomically replaces `path`, readable only by the current user on Unix since these
+/// files can hold credentials.
+pub fn write_private_file(path: &Path, content: &[u8]) -> io::Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let tmp = path.with_extension("tmp");
+    {
+        let mut options = fs::OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o600);
+        }
+        io::Write::write_all(&mut options.open(&tmp)?, content)?;
+    }
+    fs::rename(tmp, path)
+}
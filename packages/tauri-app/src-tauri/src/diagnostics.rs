@@ -0,0 +1,261 @@
+use crate::cli_manager::{CliEntry, CliProcessManager};
+use crate::config::{resolve_config_path, resolve_desktop_config_path};
+use crate::logs::{self, format_timestamp, now_millis};
+use crate::node;
+use crate::profiles;
+use crate::user_shell::{self, UserShell};
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::env;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use tar::{Builder, Header};
+use tauri::AppHandle;
+use tauri_plugin_dialog::DialogExt;
+
+const REDACTED: &str = "[REDACTED]";
+/// Rotated log files included next to the current one.
+const ROTATED_LOGS_INCLUDED: u32 = 2;
+/// Environment variables worth seeing when the server fails to start. Anything else may hold
+/// credentials and is left out.
+const ENV_VARS: &[&str] = &[
+    "PATH",
+    "SHELL",
+    "HOME",
+    "LANG",
+    "NODE_BINARY",
+    "NODE_OPTIONS",
+    "TAURI_DEV",
+    "CLI_CONFIG",
+    "AGROFORGE_LOG_LEVEL",
+    "AGROFORGE_REMOTE_PASSWORD",
+];
+
+/// Asks where to save, then writes the bundle there. `Ok(None)` means the user cancelled.
+/// Blocks on the dialog, so it must not run on the main thread.
+pub fn export_with_dialog(
+    app: &AppHandle,
+    manager: &CliProcessManager,
+    dev: bool,
+) -> Result<Option<PathBuf>, String> {
+    let stamp = format_timestamp(now_millis()).replace([':', '.'], "-");
+    let Some(destination) = app
+        .dialog()
+        .file()
+        .set_title("Export diagnostics")
+        .set_file_name(format!("agroforge-diagnostics-{stamp}.tar.gz"))
+        .add_filter("Gzipped tar archive", &["tar.gz", "tgz"])
+        .blocking_save_file()
+    else {
+        return Ok(None);
+    };
+    let path = destination
+        .into_path()
+        .map_err(|err| format!("Invalid destination: {err}"))?;
+
+    export(manager, dev, &path)
+        .map_err(|err| format!("Failed to write {}: {err}", path.display()))?;
+    log::info!(path:% = path.display(); "exported diagnostics");
+    Ok(Some(path))
+}
+
+fn export(manager: &CliProcessManager, dev: bool, path: &Path) -> io::Result<()> {
+    let mut archive = Builder::new(GzEncoder::new(File::create(path)?, Compression::default()));
+    let root = "agroforge-diagnostics";
+
+    append_json(
+        &mut archive,
+        &format!("{root}/status.json"),
+        &manager.status(),
+    )?;
+    append_json(
+        &mut archive,
+        &format!("{root}/server-output.json"),
+        &manager.logs(None, None, None),
+    )?;
+    append_json(
+        &mut archive,
+        &format!("{root}/cli-entry.json"),
+        &entry_info(dev),
+    )?;
+    append_json(
+        &mut archive,
+        &format!("{root}/environment.json"),
+        &environment(dev),
+    )?;
+    append_json(
+        &mut archive,
+        &format!("{root}/profiles.json"),
+        &profiles::list(),
+    )?;
+
+    for (name, source) in [
+        ("config.json", resolve_config_path()),
+        ("desktop.json", resolve_desktop_config_path()),
+    ] {
+        if let Some(config) = read_redacted_json(&source) {
+            append_json(&mut archive, &format!("{root}/{name}"), &config)?;
+        }
+    }
+
+    if let Some(log_path) = logs::log_file_path() {
+        let rotated = (1..=ROTATED_LOGS_INCLUDED).map(|index| {
+            let mut name = log_path.as_os_str().to_owned();
+            name.push(format!(".{index}"));
+            PathBuf::from(name)
+        });
+        for source in std::iter::once(log_path.clone()).chain(rotated) {
+            if let (Ok(content), Some(name)) = (fs::read(&source), source.file_name()) {
+                append(
+                    &mut archive,
+                    &format!("{root}/logs/{}", name.to_string_lossy()),
+                    &content,
+                )?;
+            }
+        }
+    }
+
+    archive.into_inner()?.finish()?.flush()
+}
+
+fn append_json<W: Write, T: Serialize + ?Sized>(
+    archive: &mut Builder<W>,
+    name: &str,
+    value: &T,
+) -> io::Result<()> {
+    let content = serde_json::to_vec_pretty(value).map_err(io::Error::other)?;
+    append(archive, name, &content)
+}
+
+fn append<W: Write>(archive: &mut Builder<W>, name: &str, content: &[u8]) -> io::Result<()> {
+    let mut header = Header::new_gnu();
+    header.set_size(content.len() as u64);
+    header.set_mode(0o644);
+    header.set_mtime(now_millis() / 1000);
+    archive.append_data(&mut header, name, content)
+}
+
+fn entry_info(dev: bool) -> Value {
+    match CliEntry::resolve(dev) {
+        Ok(entry) => json!({
+            "entry": entry,
+            "runtime": node::discover(),
+        }),
+        Err(err) => json!({ "error": err.to_string() }),
+    }
+}
+
+fn environment(dev: bool) -> Value {
+    let vars: serde_json::Map<String, Value> = ENV_VARS
+        .iter()
+        .filter_map(|name| {
+            let value = env::var(name).ok()?;
+            let value = if name.contains("PASSWORD") {
+                REDACTED.to_string()
+            } else {
+                value
+            };
+            Some((name.to_string(), Value::String(value)))
+        })
+        .collect();
+
+    json!({
+        "appVersion": env!("CARGO_PKG_VERSION"),
+        "os": env::consts::OS,
+        "arch": env::consts::ARCH,
+        "devMode": dev,
+        "logLevel": logs::level(),
+        "pid": std::process::id(),
+        "exportedAt": format_timestamp(now_millis()),
+        "env": vars,
+        "shell": shell_info(),
+    })
+}
+
+/// The login shell the server is launched through, whether it had to be replaced, and what
+/// became of the environment capture.
+fn shell_info() -> Value {
+    let login_env = match user_shell::login_env_outcome() {
+        None => json!({ "attempted": false }),
+        Some(Ok(env)) => json!({ "attempted": true, "captured": true, "vars": env.len() }),
+        Some(Err(err)) => json!({ "attempted": true, "captured": false, "error": err }),
+    };
+    json!({
+        "detected": UserShell::detect(),
+        "loginEnv": login_env,
+    })
+}
+
+fn read_redacted_json(path: &Path) -> Option<Value> {
+    let content = fs::read_to_string(path).ok()?;
+    let mut value = serde_json::from_str::<Value>(&content)
+        .unwrap_or_else(|err| json!({ "unparseable": err.to_string() }));
+    redact_secrets(&mut value);
+    Some(value)
+}
+
+/// Blanks out values whose key looks like it holds a credential.
+fn redact_secrets(value: &mut Value) {
+    match value {
+        Value::Object(map) => {
+            for (key, entry) in map.iter_mut() {
+                let key = key.to_ascii_lowercase();
+                let secret = ["password", "token", "secret", "apikey", "api_key", "cookie"]
+                    .iter()
+                    .any(|needle| key.contains(needle));
+                if secret && !entry.is_null() {
+                    *entry = Value::String(REDACTED.to_string());
+                } else {
+                    redact_secrets(entry);
+                }
+            }
+        }
+        Value::Array(items) => items.iter_mut().for_each(redact_secrets),
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+    use flate2::read::GzDecoder;
+    use std::io::Read;
+    use tar::Archive;
+
+    #[test]
+    fn export_writes_a_readable_archive() {
+        let path = testing::scratch_dir("diagnostics").join("bundle.tar.gz");
+        export(&CliProcessManager::new(), false, &path).unwrap();
+
+        let mut archive = Archive::new(GzDecoder::new(File::open(&path).unwrap()));
+        let mut files = std::collections::HashMap::new();
+        for entry in archive.entries().unwrap() {
+            let mut entry = entry.unwrap();
+            let name = entry.path().unwrap().display().to_string();
+            let mut content = String::new();
+            entry.read_to_string(&mut content).unwrap();
+            files.insert(name, content);
+        }
+
+        for name in [
+            "status.json",
+            "cli-entry.json",
+            "profiles.json",
+            "desktop.json",
+        ] {
+            assert!(
+                files.contains_key(&format!("agroforge-diagnostics/{name}")),
+                "{name} missing"
+            );
+        }
+        let environment: Value =
+            serde_json::from_str(&files["agroforge-diagnostics/environment.json"]).unwrap();
+        let shell = &environment["shell"];
+        assert!(shell["detected"]["kind"].is_string());
+        assert!(shell["loginEnv"]["attempted"].is_boolean());
+    }
+}
@@ -0,0 +1,269 @@
+use once_cell::sync::Lazy;
+use regex::Regex;
+use serde::Deserialize;
+use serde_json::Value;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+/// Environment variable that asks the server to print its JSON handshake line.
+pub const HANDSHAKE_ENV: &str = "AGROFORGE_HANDSHAKE";
+const HANDSHAKE_MARKER: &str = "agroforge";
+pub const PROTOCOL_VERSION: u32 = 1;
+
+static LEGACY_READY_REGEX: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"AgroForge Server is ready at https?://(?:\[[^\]]+\]|[^:/\s]+):(\d+)")
+        .expect("valid ready regex")
+});
+/// `port=1234`, as the server's stdout logger prints the fields of a record.
+static LEGACY_PORT_FIELD_REGEX: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"\bport=(\d{1,5})\b").expect("valid port field regex"));
+static LEGACY_PORT_REGEX: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r":(\d{2,5})\b").expect("valid port regex"));
+
+/// The typed payload of the server's `{"handshake":"agroforge",...}` stdout line.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReadyHandshake {
+    pub protocol_version: u32,
+    pub host: String,
+    pub port: u16,
+    pub pid: u32,
+    #[serde(default)]
+    pub server_version: Option<String>,
+    #[serde(default)]
+    pub capabilities: Vec<String>,
+}
+
+impl ReadyHandshake {
+    /// Where the app reaches the server. One listening on every interface is reached over
+    /// loopback; one bound to anything but loopback is refused.
+    pub fn base_url(&self) -> Result<String, HandshakeError> {
+        let host = self
+            .host
+            .trim()
+            .trim_start_matches('[')
+            .trim_end_matches(']');
+        let ip = if host.eq_ignore_ascii_case("localhost") {
+            IpAddr::V4(Ipv4Addr::LOCALHOST)
+        } else {
+            match host.parse::<IpAddr>() {
+                Ok(IpAddr::V4(ip)) if ip.is_unspecified() => IpAddr::V4(Ipv4Addr::LOCALHOST),
+                Ok(IpAddr::V6(ip)) if ip.is_unspecified() => IpAddr::V6(Ipv6Addr::LOCALHOST),
+                Ok(ip) if ip.is_loopback() => ip,
+                _ => return Err(HandshakeError::NotLoopback(self.host.clone())),
+            }
+        };
+        Ok(match ip {
+            IpAddr::V4(ip) => format!("http://{ip}:{}", self.port),
+            IpAddr::V6(ip) => format!("http://[{ip}]:{}", self.port),
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum ReadySignal {
+    Handshake(ReadyHandshake),
+    /// Port scraped from one of the human-readable log formats.
+    Legacy {
+        port: u16,
+    },
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum HandshakeError {
+    #[error(
+        "Server speaks handshake protocol v{found}, but this app supports v{expected}. \
+         Update AgroForge so the desktop app and server match."
+    )]
+    UnsupportedProtocol { found: u32, expected: u32 },
+    #[error("Server sent an invalid handshake: {0}")]
+    Invalid(String),
+    #[error("Server is listening on {0}, which is not a loopback address")]
+    NotLoopback(String),
+}
+
+/// Classifies a single output line. Returns `None` for lines that say nothing about readiness.
+pub fn parse_ready_line(line: &str) -> Option<Result<ReadySignal, HandshakeError>> {
+    let trimmed = line.trim();
+    if trimmed.starts_with('{') {
+        if let Ok(value) = serde_json::from_str::<Value>(trimmed) {
+            return parse_json_line(value);
+        }
+    }
+    parse_legacy_text(trimmed).map(|port| Ok(ReadySignal::Legacy { port }))
+}
+
+fn parse_json_line(value: Value) -> Option<Result<ReadySignal, HandshakeError>> {
+    if value.get("handshake").and_then(Value::as_str) == Some(HANDSHAKE_MARKER) {
+        let handshake = match serde_json::from_value::<ReadyHandshake>(value) {
+            Ok(handshake) => handshake,
+            Err(err) => return Some(Err(HandshakeError::Invalid(err.to_string()))),
+        };
+        if handshake.protocol_version != PROTOCOL_VERSION {
+            return Some(Err(HandshakeError::UnsupportedProtocol {
+                found: handshake.protocol_version,
+                expected: PROTOCOL_VERSION,
+            }));
+        }
+        if let Err(err) = handshake.base_url() {
+            return Some(Err(err));
+        }
+        return Some(Ok(ReadySignal::Handshake(handshake)));
+    }
+    None
+}
+
+fn parse_legacy_text(line: &str) -> Option<u16> {
+    if let Some(port) = LEGACY_READY_REGEX
+        .captures(line)
+        .and_then(|captures| captures.get(1))
+        .and_then(|m| m.as_str().parse::<u16>().ok())
+    {
+        return Some(port);
+    }
+
+    // The server's logger prints `[INFO] [app] HTTP server listening port=1234 host=...`.
+    if !line.to_lowercase().contains("http server listening") {
+        return None;
+    }
+    if let Some(captures) = LEGACY_PORT_FIELD_REGEX.captures(line) {
+        return captures[1].parse::<u16>().ok();
+    }
+    // Otherwise the last `:port` on the line wins, so timestamps earlier in it are skipped.
+    LEGACY_PORT_REGEX
+        .captures_iter(line)
+        .last()
+        .and_then(|captures| captures.get(1))
+        .and_then(|m| m.as_str().parse::<u16>().ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    enum Expected {
+        Handshake { port: u16 },
+        Legacy { port: u16 },
+        Unsupported { found: u32 },
+        Invalid,
+        NotLoopback,
+        Nothing,
+    }
+
+    #[test]
+    fn classifies_output_lines() {
+        use Expected::*;
+        let cases = [
+            (
+                r#"{"handshake":"agroforge","protocolVersion":1,"host":"127.0.0.1","port":9898,"pid":42,"serverVersion":"0.9.1","capabilities":["shutdown-api"]}"#,
+                Handshake { port: 9898 },
+            ),
+            (
+                r#"  {"handshake":"agroforge","protocolVersion":1,"host":"127.0.0.1","port":9898,"pid":42}  "#,
+                Handshake { port: 9898 },
+            ),
+            (
+                r#"{"handshake":"agroforge","protocolVersion":2,"host":"127.0.0.1","port":9898,"pid":42}"#,
+                Unsupported { found: 2 },
+            ),
+            (
+                r#"{"handshake":"agroforge","protocolVersion":1,"port":9898}"#,
+                Invalid,
+            ),
+            (
+                r#"{"handshake":"agroforge","protocolVersion":1,"host":"127.0.0.1","port":99999,"pid":42}"#,
+                Invalid,
+            ),
+            (
+                r#"{"handshake":"agroforge","protocolVersion":1,"host":"192.168.1.5","port":9898,"pid":42}"#,
+                NotLoopback,
+            ),
+            (r#"{"handshake":"agroforge","protocolVers"#, Nothing),
+            (r#"{"handshake":"other","port":9898}"#, Nothing),
+            (
+                "AgroForge Server is ready at http://localhost:9898",
+                Legacy { port: 9898 },
+            ),
+            (
+                "AgroForge Server is ready at http://[::1]:4321/",
+                Legacy { port: 4321 },
+            ),
+            (
+                "[INFO] [app] HTTP server listening port=9898 host=127.0.0.1",
+                Legacy { port: 9898 },
+            ),
+            (
+                "[INFO] [app] HTTP server listening port=9898 host=::1",
+                Legacy { port: 9898 },
+            ),
+            (
+                "12:30:45 HTTP server listening on 0.0.0.0:5173",
+                Legacy { port: 5173 },
+            ),
+            ("[INFO] [app] HTTP server listening", Nothing),
+            (
+                "[INFO] [workspace] opened /home/me/project port=1234",
+                Nothing,
+            ),
+            ("listening on port 9898", Nothing),
+            ("", Nothing),
+        ];
+
+        for (line, expected) in cases {
+            let parsed = parse_ready_line(line);
+            let matched = match (&parsed, &expected) {
+                (Some(Ok(ReadySignal::Handshake(handshake))), Handshake { port }) => {
+                    handshake.port == *port && handshake.protocol_version == PROTOCOL_VERSION
+                }
+                (Some(Ok(ReadySignal::Legacy { port: found })), Legacy { port }) => found == port,
+                (
+                    Some(Err(HandshakeError::UnsupportedProtocol { found, expected })),
+                    Unsupported { found: wanted },
+                ) => found == wanted && *expected == PROTOCOL_VERSION,
+                (Some(Err(HandshakeError::Invalid(_))), Invalid) => true,
+                (Some(Err(HandshakeError::NotLoopback(_))), NotLoopback) => true,
+                (None, Nothing) => true,
+                _ => false,
+            };
+            assert!(matched, "{line:?} parsed as {parsed:?}");
+        }
+    }
+
+    #[test]
+    fn handshake_fields_are_kept() {
+        let Some(Ok(ReadySignal::Handshake(handshake))) = parse_ready_line(
+            r#"{"handshake":"agroforge","protocolVersion":1,"host":"127.0.0.1","port":9898,"pid":42,"serverVersion":"0.9.1","capabilities":["shutdown-api"]}"#,
+        ) else {
+            panic!("not a handshake");
+        };
+        assert_eq!(handshake.host, "127.0.0.1");
+        assert_eq!(handshake.pid, 42);
+        assert_eq!(handshake.server_version.as_deref(), Some("0.9.1"));
+        assert_eq!(handshake.capabilities, vec!["shutdown-api".to_string()]);
+    }
+
+    #[test]
+    fn base_url_stays_on_loopback() {
+        let url = |host: &str| {
+            ReadyHandshake {
+                protocol_version: PROTOCOL_VERSION,
+                host: host.to_string(),
+                port: 9898,
+                pid: 42,
+                server_version: None,
+                capabilities: Vec::new(),
+            }
+            .base_url()
+            .ok()
+        };
+        assert_eq!(url("127.0.0.1").as_deref(), Some("http://127.0.0.1:9898"));
+        assert_eq!(url("127.0.0.2").as_deref(), Some("http://127.0.0.2:9898"));
+        assert_eq!(url("localhost").as_deref(), Some("http://127.0.0.1:9898"));
+        assert_eq!(url("0.0.0.0").as_deref(), Some("http://127.0.0.1:9898"));
+        assert_eq!(url("::1").as_deref(), Some("http://[::1]:9898"));
+        assert_eq!(url("[::1]").as_deref(), Some("http://[::1]:9898"));
+        assert_eq!(url("::").as_deref(), Some("http://[::1]:9898"));
+        assert_eq!(url("192.168.1.5"), None);
+        assert_eq!(url("example.com"), None);
+        assert_eq!(url(""), None);
+    }
+}
@@ -0,0 +1,132 @@
+use crate::config::HealthConfig;
+use crate::http_client::{HttpClient, HttpError};
+use std::time::Duration;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HealthVerdict {
+    Healthy,
+    Degraded,
+    Unresponsive,
+    /// Too many probes in a row have failed and the policy asks for a restart.
+    Restart,
+}
+
+/// Counts consecutive failed probes and maps them onto a verdict.
+#[derive(Debug)]
+pub struct HealthTracker {
+    config: HealthConfig,
+    misses: u32,
+}
+
+impl HealthTracker {
+    pub fn new(config: HealthConfig) -> Self {
+        Self { config, misses: 0 }
+    }
+
+    pub fn misses(&self) -> u32 {
+        self.misses
+    }
+
+    pub fn record(&mut self, healthy: bool) -> HealthVerdict {
+        if healthy {
+            self.misses = 0;
+            return HealthVerdict::Healthy;
+        }
+
+        self.misses += 1;
+        if self.config.restart_after > 0 && self.misses >= self.config.restart_after {
+            HealthVerdict::Restart
+        } else if self.misses >= self.config.unresponsive_after {
+            HealthVerdict::Unresponsive
+        } else if self.misses >= self.config.degraded_after {
+            HealthVerdict::Degraded
+        } else {
+            HealthVerdict::Healthy
+        }
+    }
+}
+
+/// Returns the status code of a `GET` against the server. Any response proves the event loop
+/// is alive, so callers decide which codes count as healthy.
+pub fn probe(base_url: &str, path: &str, timeout: Duration) -> Result<u16, HttpError> {
+    HttpClient::with_timeouts(timeout, timeout)
+        .get(&format!("{base_url}{path}"))
+        .map(|response| response.status)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tracker(restart_after: u32) -> HealthTracker {
+        HealthTracker::new(HealthConfig {
+            degraded_after: 2,
+            unresponsive_after: 4,
+            restart_after,
+            ..HealthConfig::default()
+        })
+    }
+
+    fn record_all(tracker: &mut HealthTracker, probes: &[bool]) -> Vec<HealthVerdict> {
+        probes
+            .iter()
+            .map(|&healthy| tracker.record(healthy))
+            .collect()
+    }
+
+    #[test]
+    fn consecutive_misses_walk_through_the_thresholds() {
+        use HealthVerdict::*;
+        let mut tracker = tracker(0);
+        assert_eq!(
+            record_all(&mut tracker, &[false, false, false, false, false]),
+            [Healthy, Degraded, Degraded, Unresponsive, Unresponsive]
+        );
+        assert_eq!(tracker.misses(), 5);
+    }
+
+    #[test]
+    fn a_healthy_probe_recovers_and_clears_the_count() {
+        use HealthVerdict::*;
+        let mut tracker = tracker(0);
+        record_all(&mut tracker, &[false, false, false, false]);
+        assert_eq!(tracker.record(true), Healthy);
+        assert_eq!(tracker.misses(), 0);
+        // Counting starts over instead of resuming where it left off.
+        assert_eq!(
+            record_all(&mut tracker, &[false, false]),
+            [Healthy, Degraded]
+        );
+    }
+
+    #[test]
+    fn restart_is_requested_after_enough_misses_when_enabled() {
+        use HealthVerdict::*;
+        let mut tracker = tracker(5);
+        assert_eq!(
+            record_all(&mut tracker, &[false, false, false, false, false, false]),
+            [Healthy, Degraded, Degraded, Unresponsive, Restart, Restart]
+        );
+    }
+
+    #[test]
+    fn restart_is_never_requested_when_disabled() {
+        let mut tracker = tracker(0);
+        let verdicts = record_all(&mut tracker, &[false; 50]);
+        assert!(!verdicts.contains(&HealthVerdict::Restart));
+    }
+
+    #[test]
+    fn probe_fails_when_nothing_is_listening() {
+        let port = std::net::TcpListener::bind("127.0.0.1:0")
+            .and_then(|listener| listener.local_addr())
+            .unwrap()
+            .port();
+        let result = probe(
+            &format!("http://127.0.0.1:{port}"),
+            "/api/meta",
+            Duration::from_millis(500),
+        );
+        assert!(result.is_err());
+    }
+}
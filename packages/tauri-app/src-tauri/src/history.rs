@@ -0,0 +1,159 @@
+use crate::cli_manager::CliState;
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::process::ExitStatus;
+
+const MAX_TRANSITIONS: usize = 200;
+const MAX_STARTUPS: usize = 20;
+
+/// Why the server's state changed.
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "kind", rename_all = "camelCase")]
+pub enum TransitionReason {
+    Start,
+    UserRestart,
+    SupervisorRestart {
+        attempt: u32,
+    },
+    SpawnFailed {
+        message: String,
+    },
+    ConnectFailed {
+        message: String,
+    },
+    Ready,
+    Adopted,
+    StartupTimeout,
+    HandshakeRejected {
+        message: String,
+    },
+    HealthCheck {
+        missed_probes: u32,
+    },
+    #[serde(rename_all = "camelCase")]
+    Exited {
+        code: Option<i32>,
+        /// Signal that killed the server, on Unix.
+        signal: Option<i32>,
+    },
+    SupervisorGaveUp {
+        reason: String,
+    },
+    StopRequested,
+    Stopped,
+}
+
+impl TransitionReason {
+    pub fn exited(status: Option<ExitStatus>) -> Self {
+        #[cfg(unix)]
+        let signal = {
+            use std::os::unix::process::ExitStatusExt;
+            status.and_then(|status| status.signal())
+        };
+        #[cfg(not(unix))]
+        let signal = None;
+        TransitionReason::Exited {
+            code: status.and_then(|status| status.code()),
+            signal,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StatusTransition {
+    /// Unix time in milliseconds.
+    pub timestamp: u64,
+    pub from: CliState,
+    pub to: CliState,
+    pub reason: TransitionReason,
+    pub pid: Option<u32>,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum StartupPhase {
+    Spawned,
+    FirstOutput,
+    Ready,
+    TokenExchange,
+    Navigated,
+}
+
+/// How long each step of one local launch took, in milliseconds since the launch began.
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StartupTimings {
+    /// Unix time in milliseconds.
+    pub started_at: u64,
+    pub pid: Option<u32>,
+    pub spawned_ms: Option<u64>,
+    pub first_output_ms: Option<u64>,
+    pub ready_ms: Option<u64>,
+    pub token_exchange_ms: Option<u64>,
+    pub navigated_ms: Option<u64>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HistorySnapshot {
+    pub transitions: Vec<StatusTransition>,
+    /// Oldest first; the last entry is the launch in progress or the most recent one.
+    pub startups: Vec<StartupTimings>,
+}
+
+/// Bounded record of state changes and launch timings, kept across restarts.
+#[derive(Debug, Default)]
+pub struct StatusHistory {
+    transitions: VecDeque<StatusTransition>,
+    startups: VecDeque<StartupTimings>,
+}
+
+impl StatusHistory {
+    pub fn record(&mut self, transition: StatusTransition) {
+        if self.transitions.len() == MAX_TRANSITIONS {
+            self.transitions.pop_front();
+        }
+        self.transitions.push_back(transition);
+    }
+
+    pub fn begin_startup(&mut self, now: u64) {
+        if self.startups.len() == MAX_STARTUPS {
+            self.startups.pop_front();
+        }
+        self.startups.push_back(StartupTimings {
+            started_at: now,
+            ..StartupTimings::default()
+        });
+    }
+
+    /// Records the first time `phase` is reached during the current launch.
+    pub fn mark(&mut self, phase: StartupPhase, now: u64) {
+        let Some(startup) = self.startups.back_mut() else {
+            return;
+        };
+        let elapsed = Some(now.saturating_sub(startup.started_at));
+        let slot = match phase {
+            StartupPhase::Spawned => &mut startup.spawned_ms,
+            StartupPhase::FirstOutput => &mut startup.first_output_ms,
+            StartupPhase::Ready => &mut startup.ready_ms,
+            StartupPhase::TokenExchange => &mut startup.token_exchange_ms,
+            StartupPhase::Navigated => &mut startup.navigated_ms,
+        };
+        if slot.is_none() {
+            *slot = elapsed;
+        }
+    }
+
+    pub fn set_startup_pid(&mut self, pid: u32) {
+        if let Some(startup) = self.startups.back_mut() {
+            startup.pid = Some(pid);
+        }
+    }
+
+    pub fn snapshot(&self) -> HistorySnapshot {
+        HistorySnapshot {
+            transitions: self.transitions.iter().cloned().collect(),
+            startups: self.startups.iter().cloned().collect(),
+        }
+    }
+}
@@ -0,0 +1,79 @@
+use serde::Serialize;
+use serde_json::Value;
+use std::sync::Arc;
+use tauri::webview::cookie::{Cookie, SameSite};
+use tauri::{AppHandle, Emitter, Manager, Url};
+
+/// What the server lifecycle needs from the desktop shell. `AppHandle` is the real host;
+/// anything else implementing this can drive `CliProcessManager` without a window.
+pub trait ServerHost: Send + Sync + std::fmt::Debug {
+    /// Sends `payload` to the frontend as `event`.
+    fn emit_value(&self, event: &str, payload: Value);
+
+    fn navigate_main(&self, url: &str);
+
+    /// Sets an HTTP-only cookie on the main window for the host of `base_url`.
+    fn set_cookie(&self, base_url: &str, name: &str, value: &str) -> anyhow::Result<()>;
+
+    /// Lets the main window load pages from `origin`, for servers that are not on loopback.
+    fn allow_origin(&self, origin: &str) -> anyhow::Result<()>;
+
+    /// Undoes `allow_origin` once the app no longer talks to the server there.
+    fn revoke_origin(&self, origin: &str);
+}
+
+pub type Host = Arc<dyn ServerHost>;
+
+impl dyn ServerHost {
+    pub fn emit(&self, event: &str, payload: impl Serialize) {
+        match serde_json::to_value(payload) {
+            Ok(value) => self.emit_value(event, value),
+            Err(err) => log::warn!(event, error:% = err; "failed to serialize event"),
+        }
+    }
+}
+
+impl ServerHost for AppHandle {
+    fn emit_value(&self, event: &str, payload: Value) {
+        let _ = Emitter::emit(self, event, payload);
+    }
+
+    fn navigate_main(&self, url: &str) {
+        if let Some(win) = self.webview_windows().get("main") {
+            log::info!(url; "navigating main window");
+            if let Ok(parsed) = Url::parse(url) {
+                let _ = win.navigate(parsed);
+            } else {
+                log::warn!(url; "failed to parse URL for navigation");
+            }
+        } else {
+            log::warn!("main window not found for navigation");
+        }
+    }
+
+    fn set_cookie(&self, base_url: &str, name: &str, value: &str) -> anyhow::Result<()> {
+        let parsed = Url::parse(base_url)?;
+        let domain = parsed.host_str().unwrap_or("127.0.0.1").to_string();
+
+        let cookie = Cookie::build((name, value))
+            .domain(domain)
+            .path("/")
+            .http_only(true)
+            .same_site(SameSite::Lax)
+            .build();
+
+        if let Some(win) = self.webview_windows().get("main") {
+            win.set_cookie(cookie)?;
+        }
+
+        Ok(())
+    }
+
+    fn allow_origin(&self, origin: &str) -> anyhow::Result<()> {
+        Ok(crate::remote::allow_origin(self, origin)?)
+    }
+
+    fn revoke_origin(&self, origin: &str) {
+        crate::remote::revoke_origin(self, origin);
+    }
+}
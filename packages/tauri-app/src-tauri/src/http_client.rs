@@ -0,0 +1,715 @@
+use once_cell::sync::Lazy;
+use rustls::pki_types::ServerName;
+use rustls::{ClientConfig, ClientConnection, RootCertStore, StreamOwned};
+use serde::Serialize;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{IpAddr, SocketAddr, TcpStream, ToSocketAddrs};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tauri::Url;
+use url::Host;
+
+const DEFAULT_CONNECT_TIMEOUT: Duration = Duration::from_secs(3);
+const DEFAULT_READ_TIMEOUT: Duration = Duration::from_secs(10);
+const MAX_HEADER_BYTES: usize = 64 * 1024;
+const MAX_BODY_BYTES: usize = 16 * 1024 * 1024;
+
+/// Built on first use, since loading the system trust store is slow. Keeps the error when no
+/// usable root certificate was found, so every https request can report it.
+static TLS_CONFIG: Lazy<Result<Arc<ClientConfig>, String>> = Lazy::new(build_tls_config);
+
+#[derive(Debug, thiserror::Error)]
+pub enum HttpError {
+    #[error("invalid URL {url}: {reason}")]
+    InvalidUrl { url: String, reason: String },
+    #[error("unsupported URL scheme \"{0}\" (only http and https are supported)")]
+    UnsupportedScheme(String),
+    #[error("could not resolve {0}")]
+    Resolve(String),
+    #[error("could not connect to {addr}: {source}")]
+    Connect {
+        addr: String,
+        #[source]
+        source: io::Error,
+    },
+    #[error("request to {0} timed out")]
+    Timeout(String),
+    #[error("I/O error: {0}")]
+    Io(#[source] io::Error),
+    #[error("TLS error: {0}")]
+    Tls(String),
+    #[error("malformed HTTP response: {0}")]
+    MalformedResponse(String),
+    #[error("failed to encode request body: {0}")]
+    Encode(#[source] serde_json::Error),
+    #[error("invalid request header \"{0}\"")]
+    InvalidHeader(String),
+}
+
+#[derive(Debug, Clone)]
+pub struct HttpRequest {
+    method: String,
+    url: Url,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+impl HttpRequest {
+    pub fn new(method: &str, url: &str) -> Result<Self, HttpError> {
+        let url = Url::parse(url).map_err(|err| HttpError::InvalidUrl {
+            url: url.to_string(),
+            reason: err.to_string(),
+        })?;
+        if !matches!(url.scheme(), "http" | "https") {
+            return Err(HttpError::UnsupportedScheme(url.scheme().to_string()));
+        }
+        Ok(Self {
+            method: method.to_ascii_uppercase(),
+            url,
+            headers: Vec::new(),
+            body: Vec::new(),
+        })
+    }
+
+    /// Fails for names or values that could end the header early, such as a session id
+    /// handed out by a remote server that contains a line break.
+    pub fn header(mut self, name: &str, value: &str) -> Result<Self, HttpError> {
+        let valid_name = !name.is_empty()
+            && name
+                .bytes()
+                .all(|byte| byte.is_ascii_graphic() && byte != b':');
+        if !valid_name || value.contains(['\r', '\n', '\0']) {
+            return Err(HttpError::InvalidHeader(name.escape_debug().to_string()));
+        }
+        self.headers.push((name.to_string(), value.to_string()));
+        Ok(self)
+    }
+
+    pub fn json<T: Serialize + ?Sized>(mut self, body: &T) -> Result<Self, HttpError> {
+        self.body = serde_json::to_vec(body).map_err(HttpError::Encode)?;
+        self.header("Content-Type", "application/json")
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct HttpResponse {
+    pub status: u16,
+    headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl HttpResponse {
+    pub fn is_success(&self) -> bool {
+        (200..300).contains(&self.status)
+    }
+
+    /// First value of a header, matched case-insensitively.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers(name).next()
+    }
+
+    /// Every value of a repeatable header such as `Set-Cookie`, matched case-insensitively.
+    pub fn headers<'a>(&'a self, name: &str) -> impl Iterator<Item = &'a str> + 'a {
+        let name = name.to_string();
+        self.headers
+            .iter()
+            .filter(move |(key, _)| key.eq_ignore_ascii_case(&name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/// Minimal blocking HTTP/1.1 client for talking to the local AgroForge server, or over TLS to a
+/// remote one. Every request uses its own connection with `Connection: close`.
+#[derive(Debug, Clone)]
+pub struct HttpClient {
+    connect_timeout: Duration,
+    /// Limit for the whole exchange once connected, not for each read.
+    read_timeout: Duration,
+}
+
+impl Default for HttpClient {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl HttpClient {
+    pub fn new() -> Self {
+        Self::with_timeouts(DEFAULT_CONNECT_TIMEOUT, DEFAULT_READ_TIMEOUT)
+    }
+
+    pub fn with_timeouts(connect_timeout: Duration, read_timeout: Duration) -> Self {
+        Self {
+            connect_timeout,
+            read_timeout,
+        }
+    }
+
+    pub fn get(&self, url: &str) -> Result<HttpResponse, HttpError> {
+        self.send(HttpRequest::new("GET", url)?)
+    }
+
+    pub fn post_json<T: Serialize + ?Sized>(
+        &self,
+        url: &str,
+        body: &T,
+    ) -> Result<HttpResponse, HttpError> {
+        self.send(HttpRequest::new("POST", url)?.json(body)?)
+    }
+
+    pub fn send(&self, request: HttpRequest) -> Result<HttpResponse, HttpError> {
+        let target = request.url.to_string();
+        let timed_out = |err: io::Error| {
+            // rustls reports handshake and certificate failures as I/O errors.
+            if let Some(tls) = err
+                .get_ref()
+                .and_then(|inner| inner.downcast_ref::<rustls::Error>())
+            {
+                HttpError::Tls(tls.to_string())
+            } else if matches!(
+                err.kind(),
+                io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock
+            ) {
+                HttpError::Timeout(target.clone())
+            } else {
+                HttpError::Io(err)
+            }
+        };
+
+        let stream = TimedSocket {
+            stream: self.connect(&request.url)?,
+            deadline: Instant::now() + self.read_timeout,
+        };
+        let mut stream = if request.url.scheme() == "https" {
+            Connection::Tls(Box::new(StreamOwned::new(
+                tls_session(&request.url)?,
+                stream,
+            )))
+        } else {
+            Connection::Plain(stream)
+        };
+
+        stream
+            .write_all(&encode_request(&request))
+            .map_err(timed_out)?;
+        stream.flush().map_err(timed_out)?;
+
+        let mut reader = BufReader::new(stream);
+        read_response(&mut reader, &request.method).map_err(|err| match err {
+            HttpError::Io(io_err) => timed_out(io_err),
+            other => other,
+        })
+    }
+
+    fn connect(&self, url: &Url) -> Result<TcpStream, HttpError> {
+        let port = url.port_or_known_default().unwrap_or(80);
+        let addrs: Vec<SocketAddr> = match url.host() {
+            Some(Host::Ipv4(ip)) => vec![SocketAddr::from((ip, port))],
+            Some(Host::Ipv6(ip)) => vec![SocketAddr::from((ip, port))],
+            Some(Host::Domain(domain)) => (domain, port)
+                .to_socket_addrs()
+                .map_err(|_| HttpError::Resolve(format!("{domain}:{port}")))?
+                .collect(),
+            None => {
+                return Err(HttpError::InvalidUrl {
+                    url: url.to_string(),
+                    reason: "missing host".to_string(),
+                })
+            }
+        };
+
+        let mut last_error = None;
+        for addr in &addrs {
+            match TcpStream::connect_timeout(addr, self.connect_timeout) {
+                Ok(stream) => return Ok(stream),
+                Err(err) => last_error = Some((addr, err)),
+            }
+        }
+        match last_error {
+            Some((addr, err)) if err.kind() == io::ErrorKind::TimedOut => {
+                Err(HttpError::Timeout(addr.to_string()))
+            }
+            Some((addr, err)) => Err(HttpError::Connect {
+                addr: addr.to_string(),
+                source: err,
+            }),
+            None => Err(HttpError::Resolve(url.to_string())),
+        }
+    }
+}
+
+/// Socket that times out once the request's deadline has passed, so a server that trickles
+/// bytes cannot keep one request going forever.
+struct TimedSocket {
+    stream: TcpStream,
+    deadline: Instant,
+}
+
+impl TimedSocket {
+    fn remaining(&self) -> io::Result<Duration> {
+        self.deadline
+            .checked_duration_since(Instant::now())
+            .filter(|remaining| !remaining.is_zero())
+            .ok_or_else(|| io::Error::from(io::ErrorKind::TimedOut))
+    }
+}
+
+impl Read for TimedSocket {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.stream.set_read_timeout(Some(self.remaining()?))?;
+        self.stream.read(buf)
+    }
+}
+
+impl Write for TimedSocket {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.stream.set_write_timeout(Some(self.remaining()?))?;
+        self.stream.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.stream.flush()
+    }
+}
+
+/// The socket of one request, wrapped in a TLS session for `https` URLs.
+enum Connection {
+    Plain(TimedSocket),
+    Tls(Box<StreamOwned<ClientConnection, TimedSocket>>),
+}
+
+impl Read for Connection {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Connection::Plain(stream) => stream.read(buf),
+            // A close without `close_notify` surfaces as `UnexpectedEof`. Bodies framed by a
+            // length never read that far; for a body that runs to the close it means the body
+            // may have been cut short, so it stays an error.
+            Connection::Tls(stream) => stream.read(buf),
+        }
+    }
+}
+
+impl Write for Connection {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Connection::Plain(stream) => stream.write(buf),
+            Connection::Tls(stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Connection::Plain(stream) => stream.flush(),
+            Connection::Tls(stream) => stream.flush(),
+        }
+    }
+}
+
+fn build_tls_config() -> Result<Arc<ClientConfig>, String> {
+    let native = rustls_native_certs::load_native_certs();
+    for err in &native.errors {
+        log::warn!(error:% = err; "failed to load system root certificates");
+    }
+    let mut roots = RootCertStore::empty();
+    let (added, _) = roots.add_parsable_certificates(native.certs);
+    if added == 0 {
+        return Err("no trusted root certificates found on this system".to_string());
+    }
+    let config =
+        ClientConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+            .with_safe_default_protocol_versions()
+            .map_err(|err| err.to_string())?
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+    Ok(Arc::new(config))
+}
+
+fn tls_session(url: &Url) -> Result<ClientConnection, HttpError> {
+    let config = TLS_CONFIG
+        .as_ref()
+        .map_err(|err| HttpError::Tls(err.clone()))?;
+    let name = match url.host() {
+        Some(Host::Domain(domain)) => ServerName::try_from(domain.to_string())
+            .map_err(|err| HttpError::Tls(format!("invalid server name {domain}: {err}")))?,
+        Some(Host::Ipv4(ip)) => ServerName::from(IpAddr::V4(ip)),
+        Some(Host::Ipv6(ip)) => ServerName::from(IpAddr::V6(ip)),
+        None => {
+            return Err(HttpError::InvalidUrl {
+                url: url.to_string(),
+                reason: "missing host".to_string(),
+            })
+        }
+    };
+    ClientConnection::new(config.clone(), name).map_err(|err| HttpError::Tls(err.to_string()))
+}
+
+fn encode_request(request: &HttpRequest) -> Vec<u8> {
+    let url = &request.url;
+    let mut target = url.path().to_string();
+    if let Some(query) = url.query() {
+        target.push('?');
+        target.push_str(query);
+    }
+    // `host_str` keeps the brackets around IPv6 literals, as the Host header requires.
+    let host = url.host_str().unwrap_or_default();
+    let host_header = match url.port() {
+        Some(port) => format!("{host}:{port}"),
+        None => host.to_string(),
+    };
+
+    let mut head = format!(
+        "{} {target} HTTP/1.1\r\nHost: {host_header}\r\n",
+        request.method
+    );
+    for (name, value) in &request.headers {
+        head.push_str(&format!("{name}: {value}\r\n"));
+    }
+    if !request.body.is_empty() || matches!(request.method.as_str(), "POST" | "PUT" | "PATCH") {
+        head.push_str(&format!("Content-Length: {}\r\n", request.body.len()));
+    }
+    head.push_str("Connection: close\r\n\r\n");
+
+    let mut bytes = head.into_bytes();
+    bytes.extend_from_slice(&request.body);
+    bytes
+}
+
+fn read_response<R: BufRead>(reader: &mut R, method: &str) -> Result<HttpResponse, HttpError> {
+    let mut header_bytes = 0usize;
+    let status_line = read_head_line(reader, &mut header_bytes)?;
+    let mut parts = status_line.splitn(3, ' ');
+    let version = parts.next().unwrap_or_default();
+    if !version.starts_with("HTTP/1.") {
+        return Err(HttpError::MalformedResponse(format!(
+            "unexpected status line \"{status_line}\""
+        )));
+    }
+    let status = parts
+        .next()
+        .and_then(|code| code.parse::<u16>().ok())
+        .ok_or_else(|| {
+            HttpError::MalformedResponse(format!("invalid status code in \"{status_line}\""))
+        })?;
+
+    let mut headers = Vec::new();
+    loop {
+        let line = read_head_line(reader, &mut header_bytes)?;
+        if line.is_empty() {
+            break;
+        }
+        let (name, value) = line
+            .split_once(':')
+            .ok_or_else(|| HttpError::MalformedResponse(format!("invalid header \"{line}\"")))?;
+        headers.push((name.trim().to_string(), value.trim().to_string()));
+    }
+
+    let mut response = HttpResponse {
+        status,
+        headers,
+        body: Vec::new(),
+    };
+
+    let has_body = method != "HEAD" && !matches!(status, 100..=199 | 204 | 304);
+    if !has_body {
+        return Ok(response);
+    }
+
+    let chunked = response
+        .headers("transfer-encoding")
+        .any(|value| value.to_ascii_lowercase().contains("chunked"));
+    response.body = if chunked {
+        read_chunked_body(reader)?
+    } else if let Some(length) = response.header("content-length") {
+        let length = length.parse::<usize>().map_err(|_| {
+            HttpError::MalformedResponse(format!("invalid Content-Length \"{length}\""))
+        })?;
+        if length > MAX_BODY_BYTES {
+            return Err(HttpError::MalformedResponse(format!(
+                "body of {length} bytes exceeds limit"
+            )));
+        }
+        let mut body = vec![0u8; length];
+        reader.read_exact(&mut body).map_err(HttpError::Io)?;
+        body
+    } else {
+        let mut body = Vec::new();
+        (&mut *reader)
+            .take(MAX_BODY_BYTES as u64)
+            .read_to_end(&mut body)
+            .map_err(|err| match err.kind() {
+                io::ErrorKind::UnexpectedEof => HttpError::MalformedResponse(
+                    "connection closed without TLS close_notify; the body may be truncated"
+                        .to_string(),
+                ),
+                _ => HttpError::Io(err),
+            })?;
+        body
+    };
+
+    Ok(response)
+}
+
+fn read_head_line<R: BufRead>(reader: &mut R, consumed: &mut usize) -> Result<String, HttpError> {
+    let mut line = Vec::new();
+    let read = (&mut *reader)
+        .take(MAX_HEADER_BYTES.saturating_sub(*consumed) as u64)
+        .read_until(b'\n', &mut line)
+        .map_err(HttpError::Io)?;
+    *consumed += read;
+    if read == 0 {
+        return Err(HttpError::MalformedResponse(
+            "connection closed before headers were complete".to_string(),
+        ));
+    }
+    if !line.ends_with(b"\n") {
+        return Err(HttpError::MalformedResponse(
+            "response headers are too large".to_string(),
+        ));
+    }
+    let text = String::from_utf8_lossy(&line);
+    Ok(text.trim_end_matches(['\r', '\n']).to_string())
+}
+
+fn read_chunked_body<R: BufRead>(reader: &mut R) -> Result<Vec<u8>, HttpError> {
+    let mut body = Vec::new();
+    let mut header_bytes = 0usize;
+    loop {
+        let size_line = read_head_line(reader, &mut header_bytes)?;
+        let size_field = size_line.split(';').next().unwrap_or_default().trim();
+        let size = usize::from_str_radix(size_field, 16).map_err(|_| {
+            HttpError::MalformedResponse(format!("invalid chunk size \"{size_line}\""))
+        })?;
+        if size == 0 {
+            // Skip optional trailers up to the terminating blank line.
+            while !read_head_line(reader, &mut header_bytes)?.is_empty() {}
+            return Ok(body);
+        }
+        // A hostile size such as `ffffffffffffffff` must not wrap around the limit check.
+        if body
+            .len()
+            .checked_add(size)
+            .is_none_or(|total| total > MAX_BODY_BYTES)
+        {
+            return Err(HttpError::MalformedResponse(
+                "chunked body exceeds limit".to_string(),
+            ));
+        }
+
+        let start = body.len();
+        body.resize(start + size, 0);
+        reader
+            .read_exact(&mut body[start..])
+            .map_err(HttpError::Io)?;
+        let mut crlf = [0u8; 2];
+        reader.read_exact(&mut crlf).map_err(HttpError::Io)?;
+        if &crlf != b"\r\n" {
+            return Err(HttpError::MalformedResponse(
+                "chunk is not terminated by CRLF".to_string(),
+            ));
+        }
+        header_bytes = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn parse(raw: &str) -> Result<HttpResponse, HttpError> {
+        read_response(&mut Cursor::new(raw.as_bytes()), "GET")
+    }
+
+    #[test]
+    fn reads_chunked_body_and_skips_trailers() {
+        let response = parse(
+            "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n\
+             5\r\nhello\r\n7;ext=1\r\n, world\r\n0\r\nX-Trailer: 1\r\n\r\n",
+        )
+        .unwrap();
+        assert_eq!(response.status, 200);
+        assert_eq!(response.body, b"hello, world");
+    }
+
+    #[test]
+    fn reads_exactly_content_length_bytes() {
+        let response =
+            parse("HTTP/1.1 201 Created\r\nContent-Length: 5\r\n\r\nhello, trailing").unwrap();
+        assert_eq!(response.status, 201);
+        assert_eq!(response.body, b"hello");
+    }
+
+    #[test]
+    fn reads_to_eof_without_length() {
+        let response = parse("HTTP/1.0 200 OK\r\nConnection: close\r\n\r\nuntil eof").unwrap();
+        assert_eq!(response.body, b"until eof");
+    }
+
+    #[test]
+    fn no_content_has_no_body() {
+        let response = parse("HTTP/1.1 204 No Content\r\n\r\nignored").unwrap();
+        assert!(response.body.is_empty());
+    }
+
+    #[test]
+    fn headers_match_case_insensitively() {
+        let response = parse(
+            "HTTP/1.1 200 OK\r\nset-cookie: a=1\r\nSET-COOKIE: b=2\r\nSet-Cookie: c=3\r\n\
+             Content-Length: 0\r\n\r\n",
+        )
+        .unwrap();
+        assert_eq!(
+            response.headers("Set-Cookie").collect::<Vec<_>>(),
+            vec!["a=1", "b=2", "c=3"]
+        );
+        assert_eq!(response.header("CONTENT-LENGTH"), Some("0"));
+    }
+
+    #[test]
+    fn rejects_chunk_size_that_would_overflow() {
+        let err = parse(
+            "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n\
+             1\r\na\r\nffffffffffffffff\r\n",
+        )
+        .unwrap_err();
+        assert!(matches!(err, HttpError::MalformedResponse(_)), "{err}");
+    }
+
+    #[test]
+    fn rejects_garbage_status_line() {
+        let err = parse("SSH-2.0-OpenSSH\r\n\r\n").unwrap_err();
+        assert!(matches!(err, HttpError::MalformedResponse(_)), "{err}");
+    }
+
+    #[test]
+    fn host_header_keeps_ipv6_brackets_and_port() {
+        let request = HttpRequest::new("post", "http://[::1]:8080/api/meta?x=1")
+            .unwrap()
+            .json(&serde_json::json!({}))
+            .unwrap();
+        let encoded = String::from_utf8(encode_request(&request)).unwrap();
+        assert!(encoded.starts_with("POST /api/meta?x=1 HTTP/1.1\r\nHost: [::1]:8080\r\n"));
+        assert!(encoded.contains("Content-Length: 2\r\n"));
+        assert!(encoded.ends_with("Connection: close\r\n\r\n{}"));
+    }
+
+    #[test]
+    fn only_http_and_https_are_accepted() {
+        assert!(HttpRequest::new("GET", "https://example.com/").is_ok());
+        assert!(matches!(
+            HttpRequest::new("GET", "ftp://example.com/"),
+            Err(HttpError::UnsupportedScheme(scheme)) if scheme == "ftp"
+        ));
+    }
+
+    #[test]
+    fn https_to_a_plain_http_server_fails_as_tls_error() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        std::thread::spawn(move || {
+            if let Ok((mut stream, _)) = listener.accept() {
+                let _ = stream.read(&mut [0; 1024]);
+                let _ = stream.write_all(b"HTTP/1.1 400 Bad Request\r\nContent-Length: 0\r\n\r\n");
+            }
+        });
+
+        let err = HttpClient::new()
+            .get(&format!("https://127.0.0.1:{port}/"))
+            .unwrap_err();
+        assert!(matches!(err, HttpError::Tls(_)), "{err}");
+    }
+
+    /// Serves `data`, then fails the way rustls does when the peer closes without
+    /// `close_notify`.
+    struct NoCloseNotify(Cursor<Vec<u8>>);
+
+    impl Read for NoCloseNotify {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            match self.0.read(buf)? {
+                0 => Err(io::ErrorKind::UnexpectedEof.into()),
+                read => Ok(read),
+            }
+        }
+    }
+
+    fn parse_without_close_notify(raw: &str) -> Result<HttpResponse, HttpError> {
+        let reader = NoCloseNotify(Cursor::new(raw.as_bytes().to_vec()));
+        read_response(&mut BufReader::new(reader), "GET")
+    }
+
+    #[test]
+    fn missing_close_notify_is_fine_for_framed_bodies() {
+        let response =
+            parse_without_close_notify("HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nhello")
+                .unwrap();
+        assert_eq!(response.body, b"hello");
+
+        let response = parse_without_close_notify(
+            "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nhello\r\n0\r\n\r\n",
+        )
+        .unwrap();
+        assert_eq!(response.body, b"hello");
+    }
+
+    #[test]
+    fn missing_close_notify_rejects_a_body_that_runs_to_the_close() {
+        let err = parse_without_close_notify("HTTP/1.1 200 OK\r\n\r\ncut sh").unwrap_err();
+        assert!(matches!(err, HttpError::MalformedResponse(_)), "{err}");
+    }
+
+    #[test]
+    fn headers_with_line_breaks_are_rejected() {
+        let request = || HttpRequest::new("GET", "http://127.0.0.1/").unwrap();
+        assert!(request().header("Cookie", "session=abc").is_ok());
+        for (name, value) in [
+            ("Cookie", "session=abc\r\nX-Injected: 1"),
+            ("Cookie", "session=abc\nX-Injected: 1"),
+            ("Cookie", "session=abc\r"),
+            ("X-Evil\r\nX-Injected", "1"),
+            ("X-Evil: 1\r\n", "1"),
+            ("", "1"),
+        ] {
+            assert!(
+                matches!(
+                    request().header(name, value),
+                    Err(HttpError::InvalidHeader(_))
+                ),
+                "{name:?}: {value:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn slow_server_cannot_outlast_the_overall_timeout() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        std::thread::spawn(move || {
+            if let Ok((mut stream, _)) = listener.accept() {
+                let _ = stream.read(&mut [0; 1024]);
+                // Each byte arrives well within the timeout; the whole response never does.
+                for byte in b"HTTP/1.1 200 OK\r\nX-Padding: ".iter().cycle().take(100) {
+                    if stream.write_all(&[*byte]).is_err() {
+                        return;
+                    }
+                    std::thread::sleep(Duration::from_millis(100));
+                }
+            }
+        });
+
+        let started = Instant::now();
+        let err = HttpClient::with_timeouts(Duration::from_secs(1), Duration::from_millis(500))
+            .get(&format!("http://127.0.0.1:{port}/"))
+            .unwrap_err();
+        assert!(matches!(err, HttpError::Timeout(_)), "{err}");
+        assert!(started.elapsed() < Duration::from_secs(3));
+    }
+
+    #[test]
+    fn host_header_omits_default_port() {
+        let request = HttpRequest::new("GET", "http://localhost:80/").unwrap();
+        let encoded = String::from_utf8(encode_request(&request)).unwrap();
+        assert!(encoded.contains("\r\nHost: localhost\r\n"));
+        assert!(!encoded.contains("Content-Length"));
+    }
+}
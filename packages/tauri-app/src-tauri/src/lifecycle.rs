@@ -0,0 +1,169 @@
+use parking_lot::{Condvar, Mutex};
+use std::process::{Child, ExitStatus};
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+enum Command {
+    Kill,
+}
+
+/// Outcome of one spawn, filled in once by the owner thread.
+#[derive(Default)]
+struct ExitSlot {
+    /// `Some(None)` when the exit could not be observed, e.g. `try_wait` failed.
+    status: Mutex<Option<Option<ExitStatus>>>,
+    exited: Condvar,
+}
+
+/// Cheap handle to a server process whose `Child` lives on a thread of its own. Nothing else
+/// ever touches the `Child`, so killing or waiting never contends with the reaper.
+#[derive(Clone)]
+pub struct ChildHandle {
+    pid: u32,
+    generation: u64,
+    commands: Sender<Command>,
+    exit: Arc<ExitSlot>,
+}
+
+impl std::fmt::Debug for ChildHandle {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ChildHandle")
+            .field("pid", &self.pid)
+            .field("generation", &self.generation)
+            .finish()
+    }
+}
+
+impl ChildHandle {
+    /// Moves `child` onto an owner thread that reaps it and then calls `on_exit` with its
+    /// status. Take stdout/stderr before handing it over.
+    pub fn spawn_owner(
+        mut child: Child,
+        generation: u64,
+        on_exit: impl FnOnce(Option<ExitStatus>) + Send + 'static,
+    ) -> Self {
+        let (commands, receiver) = mpsc::channel();
+        let handle = Self {
+            pid: child.id(),
+            generation,
+            commands,
+            exit: Arc::new(ExitSlot::default()),
+        };
+
+        let exit = handle.exit.clone();
+        thread::spawn(move || {
+            let status = loop {
+                match child.try_wait() {
+                    Ok(Some(status)) => break Some(status),
+                    Ok(None) => {}
+                    Err(err) => {
+                        log::warn!(pid = child.id(), error:% = err; "failed to wait for server");
+                        break None;
+                    }
+                }
+                match receiver.recv_timeout(POLL_INTERVAL) {
+                    Ok(Command::Kill) => {
+                        let _ = child.kill();
+                    }
+                    Err(RecvTimeoutError::Timeout) => {}
+                    // Every handle is gone; keep reaping so the process does not linger.
+                    Err(RecvTimeoutError::Disconnected) => thread::sleep(POLL_INTERVAL),
+                }
+            };
+            *exit.status.lock() = Some(status);
+            exit.exited.notify_all();
+            on_exit(status);
+        });
+
+        handle
+    }
+
+    pub fn pid(&self) -> u32 {
+        self.pid
+    }
+
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    /// Asks the owner thread to kill the process. Does not wait for it to exit.
+    pub fn kill(&self) {
+        let _ = self.commands.send(Command::Kill);
+    }
+
+    /// Blocks until the process has been reaped or `timeout` passes. Returns whether it exited.
+    pub fn wait_timeout(&self, timeout: Duration) -> bool {
+        let deadline = Instant::now() + timeout;
+        let mut status = self.exit.status.lock();
+        while status.is_none() {
+            if self
+                .exit
+                .exited
+                .wait_until(&mut status, deadline)
+                .timed_out()
+            {
+                break;
+            }
+        }
+        status.is_some()
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::process_tree::is_alive;
+    use std::process::Command;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    #[test]
+    fn kill_wakes_every_waiter_and_reports_the_exit_once() {
+        let child = Command::new("sleep").arg("30").spawn().unwrap();
+        let exits = Arc::new(AtomicU32::new(0));
+        let (sender, receiver) = mpsc::channel();
+        let counted = exits.clone();
+        let handle = ChildHandle::spawn_owner(child, 7, move |status| {
+            counted.fetch_add(1, Ordering::SeqCst);
+            let _ = sender.send(status);
+        });
+        assert_eq!(handle.generation(), 7);
+        assert!(!handle.wait_timeout(Duration::from_millis(200)));
+
+        let waiters: Vec<_> = (0..3)
+            .map(|_| {
+                let handle = handle.clone();
+                thread::spawn(move || handle.wait_timeout(Duration::from_secs(10)))
+            })
+            .collect();
+        handle.kill();
+        for waiter in waiters {
+            assert!(waiter.join().unwrap());
+        }
+
+        let status = receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert!(status.is_some_and(|status| !status.success()));
+        assert!(!is_alive(handle.pid()));
+        // Further kills are ignored once the process is gone.
+        handle.kill();
+        thread::sleep(POLL_INTERVAL * 3);
+        assert_eq!(exits.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn natural_exit_is_reaped_with_its_status() {
+        let child = Command::new("sh").args(["-c", "exit 4"]).spawn().unwrap();
+        let (sender, receiver) = mpsc::channel();
+        let handle = ChildHandle::spawn_owner(child, 1, move |status| {
+            let _ = sender.send(status);
+        });
+
+        assert!(handle.wait_timeout(Duration::from_secs(5)));
+        let status = receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(status.and_then(|status| status.code()), Some(4));
+        assert!(handle.wait_timeout(Duration::ZERO));
+    }
+}
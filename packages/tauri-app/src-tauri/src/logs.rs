@@ -0,0 +1,639 @@
+use crate::config::{load_desktop_config, LoggingConfig};
+use log::kv::{Error as KvError, Key, Value as KvValue, VisitSource};
+use log::{LevelFilter, Metadata, Record};
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::VecDeque;
+use std::env;
+use std::fmt::Write as _;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const LOG_FILENAME: &str = "agroforge.log";
+const LOG_LEVEL_ENV: &str = "AGROFORGE_LOG_LEVEL";
+/// Target used for lines relayed from the server's stdout and stderr.
+pub const SERVER_TARGET: &str = "server";
+
+/// How many server output lines are kept in memory.
+const LOG_BUFFER_CAPACITY: usize = 2_000;
+/// Longer lines are cut so one runaway line cannot hold on to megabytes.
+const MAX_LINE_BYTES: usize = 8 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogStream {
+    Stdout,
+    Stderr,
+}
+
+impl LogStream {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            LogStream::Stdout => "stdout",
+            LogStream::Stderr => "stderr",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogLevel {
+    Trace,
+    Debug,
+    Info,
+    #[serde(alias = "warning")]
+    Warn,
+    #[serde(alias = "fatal")]
+    Error,
+}
+
+impl LogLevel {
+    pub fn parse(label: &str) -> Option<Self> {
+        match label.trim().to_ascii_lowercase().as_str() {
+            "trace" => Some(LogLevel::Trace),
+            "debug" => Some(LogLevel::Debug),
+            "info" => Some(LogLevel::Info),
+            "warn" | "warning" => Some(LogLevel::Warn),
+            "error" | "fatal" => Some(LogLevel::Error),
+            _ => None,
+        }
+    }
+
+    /// Name understood by the server's `--log-level`.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            LogLevel::Trace => "trace",
+            LogLevel::Debug => "debug",
+            LogLevel::Info => "info",
+            LogLevel::Warn => "warn",
+            LogLevel::Error => "error",
+        }
+    }
+}
+
+impl From<LogLevel> for log::Level {
+    fn from(level: LogLevel) -> Self {
+        match level {
+            LogLevel::Trace => log::Level::Trace,
+            LogLevel::Debug => log::Level::Debug,
+            LogLevel::Info => log::Level::Info,
+            LogLevel::Warn => log::Level::Warn,
+            LogLevel::Error => log::Level::Error,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LogEntry {
+    /// Increases by one per line; pass the last one seen as `since` to fetch only newer lines.
+    pub seq: u64,
+    /// Unix time in milliseconds.
+    pub timestamp: u64,
+    pub stream: LogStream,
+    pub level: LogLevel,
+    pub message: String,
+}
+
+/// Bounded history of the server's output, oldest lines dropped first.
+#[derive(Debug)]
+pub struct LogBuffer {
+    entries: VecDeque<LogEntry>,
+    next_seq: u64,
+}
+
+impl LogBuffer {
+    pub fn new() -> Self {
+        Self {
+            entries: VecDeque::with_capacity(LOG_BUFFER_CAPACITY),
+            next_seq: 1,
+        }
+    }
+
+    pub fn push(&mut self, stream: LogStream, line: &str, timestamp: u64) -> LogEntry {
+        let mut message = line.to_string();
+        if message.len() > MAX_LINE_BYTES {
+            let mut end = MAX_LINE_BYTES;
+            while !message.is_char_boundary(end) {
+                end -= 1;
+            }
+            message.truncate(end);
+            message.push('…');
+        }
+
+        let entry = LogEntry {
+            seq: self.next_seq,
+            timestamp,
+            stream,
+            level: parse_level(line),
+            message,
+        };
+        self.next_seq += 1;
+        if self.entries.len() == LOG_BUFFER_CAPACITY {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(entry.clone());
+        entry
+    }
+
+    /// The newest `limit` entries after `since`, oldest first.
+    pub fn query(
+        &self,
+        since: Option<u64>,
+        limit: Option<usize>,
+        stream: Option<LogStream>,
+    ) -> Vec<LogEntry> {
+        let mut matching: Vec<LogEntry> = self
+            .entries
+            .iter()
+            .rev()
+            .take_while(|entry| since.is_none_or(|since| entry.seq > since))
+            .filter(|entry| stream.is_none_or(|stream| entry.stream == stream))
+            .take(limit.unwrap_or(usize::MAX))
+            .cloned()
+            .collect();
+        matching.reverse();
+        matching
+    }
+}
+
+/// Reads the level from pino's JSON lines, or guesses it from a plain-text line.
+fn parse_level(line: &str) -> LogLevel {
+    let trimmed = line.trim_start();
+    if trimmed.starts_with('{') {
+        if let Ok(value) = serde_json::from_str::<Value>(trimmed) {
+            match value.get("level") {
+                Some(Value::Number(number)) => {
+                    return match number.as_u64().unwrap_or(30) {
+                        0..=10 => LogLevel::Trace,
+                        11..=20 => LogLevel::Debug,
+                        21..=30 => LogLevel::Info,
+                        31..=40 => LogLevel::Warn,
+                        _ => LogLevel::Error,
+                    };
+                }
+                Some(Value::String(label)) => return level_from_label(label),
+                _ => {}
+            }
+        }
+    }
+
+    for word in line.split(|c: char| !c.is_ascii_alphabetic()).take(8) {
+        match word {
+            "ERROR" | "FATAL" | "ERR" => return LogLevel::Error,
+            "WARN" | "WARNING" => return LogLevel::Warn,
+            "DEBUG" => return LogLevel::Debug,
+            "TRACE" => return LogLevel::Trace,
+            "INFO" => return LogLevel::Info,
+            _ => {}
+        }
+    }
+    LogLevel::Info
+}
+
+fn level_from_label(label: &str) -> LogLevel {
+    LogLevel::parse(label).unwrap_or(LogLevel::Info)
+}
+
+/// Log file shared by every log record; `None` until `init_log_file` has run.
+static LOG_FILE: Lazy<Mutex<Option<LogFile>>> = Lazy::new(|| Mutex::new(None));
+
+static LOGGER: ShellLogger = ShellLogger;
+
+/// Installs the logger. The level comes from `AGROFORGE_LOG_LEVEL`, then `logging.level` in
+/// `desktop.json`, then `debug` in development and `info` otherwise.
+pub fn init(dev: bool) {
+    let _ = log::set_logger(&LOGGER);
+    // Lets config loading report problems while the real level is being worked out.
+    log::set_max_level(LevelFilter::Info);
+
+    let from_env = env::var(LOG_LEVEL_ENV)
+        .ok()
+        .filter(|value| !value.is_empty());
+    let env_level = from_env.as_deref().and_then(LogLevel::parse);
+    if let (Some(value), None) = (&from_env, env_level) {
+        log::warn!(value = value.as_str(); "ignoring invalid {LOG_LEVEL_ENV}");
+    }
+    let level = env_level
+        .or(load_desktop_config().logging.level)
+        .unwrap_or(if dev { LogLevel::Debug } else { LogLevel::Info });
+    log::set_max_level(log::Level::from(level).to_level_filter());
+}
+
+/// The level the app logs at, which the spawned server is asked to use as well.
+pub fn level() -> LogLevel {
+    match log::max_level() {
+        LevelFilter::Trace => LogLevel::Trace,
+        LevelFilter::Debug => LogLevel::Debug,
+        LevelFilter::Info => LogLevel::Info,
+        LevelFilter::Warn => LogLevel::Warn,
+        LevelFilter::Error | LevelFilter::Off => LogLevel::Error,
+    }
+}
+
+/// Writes records to stdout and the log file as `LEVEL target: message key=value…`. URL
+/// fragments are redacted because they can carry bootstrap tokens.
+struct ShellLogger;
+
+impl log::Log for ShellLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        // Dependencies log plenty at debug level; only their warnings are worth keeping.
+        let ours = metadata.target() == SERVER_TARGET
+            || metadata.target().starts_with(env!("CARGO_CRATE_NAME"));
+        metadata.level() <= log::max_level() && (ours || metadata.level() <= log::Level::Warn)
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let mut fields = FieldWriter(String::new());
+        let _ = record.key_values().visit(&mut fields);
+        let line = redact_url_fragments(&format!(
+            "{:<5} {}: {}{}",
+            record.level(),
+            short_target(record.target()),
+            record.args(),
+            fields.0
+        ));
+
+        println!("[tauri-cli] {line}");
+        if let Some(file) = LOG_FILE.lock().as_mut() {
+            file.append(&format!("{} {line}\n", format_timestamp(now_millis())));
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+/// `codenomad_tauri::cli_manager` becomes `cli_manager`; the crate root becomes `app`.
+fn short_target(target: &str) -> &str {
+    match target.strip_prefix(env!("CARGO_CRATE_NAME")) {
+        Some("") => "app",
+        Some(rest) => rest.trim_start_matches("::"),
+        None => target,
+    }
+}
+
+struct FieldWriter(String);
+
+impl<'kvs> VisitSource<'kvs> for FieldWriter {
+    fn visit_pair(&mut self, key: Key<'kvs>, value: KvValue<'kvs>) -> Result<(), KvError> {
+        let value = value.to_string();
+        if value.is_empty() || value.contains(char::is_whitespace) {
+            let _ = write!(self.0, " {key}={value:?}");
+        } else {
+            let _ = write!(self.0, " {key}={value}");
+        }
+        Ok(())
+    }
+}
+
+/// Opens (or continues) the log file in `dir`. Messages logged before this are only printed.
+pub fn init_log_file(dir: &Path, config: &LoggingConfig) -> io::Result<PathBuf> {
+    fs::create_dir_all(dir)?;
+    let file = LogFile::open(dir.join(LOG_FILENAME), config.clone())?;
+    let path = file.path.clone();
+    *LOG_FILE.lock() = Some(file);
+    Ok(path)
+}
+
+pub fn log_file_path() -> Option<PathBuf> {
+    LOG_FILE.lock().as_ref().map(|file| file.path.clone())
+}
+
+/// Replaces everything after the `#` of URLs in `text` with `[REDACTED]`.
+pub fn redact_url_fragments(text: &str) -> String {
+    let mut redacted = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(scheme_end) = rest.find("://") {
+        let url_end = rest[scheme_end..]
+            .find(|c: char| c.is_whitespace() || c == '"' || c == '\'')
+            .map_or(rest.len(), |offset| scheme_end + offset);
+        match rest[scheme_end..url_end].find('#') {
+            Some(offset) => {
+                redacted.push_str(&rest[..scheme_end + offset + 1]);
+                redacted.push_str("[REDACTED]");
+            }
+            None => redacted.push_str(&rest[..url_end]),
+        }
+        rest = &rest[url_end..];
+    }
+    redacted.push_str(rest);
+    redacted
+}
+
+/// Append-only file that is rotated to `<name>.1`, `<name>.2`, … once it reaches the
+/// configured size.
+struct LogFile {
+    path: PathBuf,
+    file: File,
+    size: u64,
+    config: LoggingConfig,
+}
+
+impl LogFile {
+    fn open(path: PathBuf, config: LoggingConfig) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let size = file.metadata()?.len();
+        Ok(Self {
+            path,
+            file,
+            size,
+            config,
+        })
+    }
+
+    fn append(&mut self, line: &str) {
+        let len = line.len() as u64;
+        if self.size > 0 && self.size + len > self.config.max_file_bytes {
+            if let Err(err) = self.rotate() {
+                // Logging from here would re-enter the file lock.
+                eprintln!(
+                    "[tauri-cli] failed to rotate {}: {err}",
+                    self.path.display()
+                );
+            }
+        }
+        if self.file.write_all(line.as_bytes()).is_ok() {
+            self.size += len;
+        }
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        let keep = self.config.max_files;
+        if keep > 0 {
+            let _ = fs::remove_file(self.rotated_path(keep));
+            for index in (1..keep).rev() {
+                let from = self.rotated_path(index);
+                if from.exists() {
+                    fs::rename(&from, self.rotated_path(index + 1))?;
+                }
+            }
+            fs::rename(&self.path, self.rotated_path(1))?;
+        }
+        self.file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(true)
+            .open(&self.path)?;
+        self.size = 0;
+        Ok(())
+    }
+
+    fn rotated_path(&self, index: u32) -> PathBuf {
+        let mut name = self.path.as_os_str().to_owned();
+        name.push(format!(".{index}"));
+        PathBuf::from(name)
+    }
+}
+
+/// Unix time in milliseconds, the clock behind every timestamp the app records.
+pub fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+/// RFC 3339 in UTC, e.g. `2024-05-01T12:34:56.789Z`.
+pub fn format_timestamp(millis: u64) -> String {
+    let secs = millis / 1000;
+    let (hour, minute, second) = (secs / 3600 % 24, secs / 60 % 60, secs % 60);
+
+    // Civil date from days since the epoch (Howard Hinnant's algorithm).
+    let days = (secs / 86_400) as i64 + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{year:04}-{month:02}-{day:02}T{hour:02}:{minute:02}:{second:02}.{:03}Z",
+        millis % 1000
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+
+    fn seqs(entries: &[LogEntry]) -> Vec<u64> {
+        entries.iter().map(|entry| entry.seq).collect()
+    }
+
+    fn buffer_with(streams: &[LogStream]) -> LogBuffer {
+        let mut buffer = LogBuffer::new();
+        for (index, &stream) in streams.iter().enumerate() {
+            buffer.push(stream, &format!("line {}", index + 1), index as u64);
+        }
+        buffer
+    }
+
+    #[test]
+    fn buffer_drops_the_oldest_lines_at_capacity() {
+        let mut buffer = LogBuffer::new();
+        for index in 0..LOG_BUFFER_CAPACITY + 5 {
+            buffer.push(LogStream::Stdout, &format!("line {index}"), 0);
+        }
+
+        let entries = buffer.query(None, None, None);
+        assert_eq!(entries.len(), LOG_BUFFER_CAPACITY);
+        assert_eq!(entries[0].seq, 6);
+        assert_eq!(entries[0].message, "line 5");
+        assert_eq!(
+            entries.last().map(|entry| entry.seq),
+            Some(LOG_BUFFER_CAPACITY as u64 + 5)
+        );
+    }
+
+    #[test]
+    fn buffer_truncates_long_lines_on_a_char_boundary() {
+        let mut buffer = LogBuffer::new();
+        let entry = buffer.push(LogStream::Stdout, &"é".repeat(MAX_LINE_BYTES), 0);
+        assert!(entry.message.ends_with('…'));
+        assert!(entry.message.len() <= MAX_LINE_BYTES + '…'.len_utf8());
+    }
+
+    #[test]
+    fn query_filters_by_since_limit_and_stream() {
+        use LogStream::*;
+        let buffer = buffer_with(&[Stdout, Stderr, Stdout, Stderr, Stdout, Stdout]);
+
+        assert_eq!(seqs(&buffer.query(None, None, None)), [1, 2, 3, 4, 5, 6]);
+        assert_eq!(seqs(&buffer.query(Some(4), None, None)), [5, 6]);
+        assert!(buffer.query(Some(6), None, None).is_empty());
+        // The limit keeps the newest entries but still returns them oldest first.
+        assert_eq!(seqs(&buffer.query(None, Some(2), None)), [5, 6]);
+        assert_eq!(seqs(&buffer.query(None, None, Some(Stderr))), [2, 4]);
+        assert_eq!(seqs(&buffer.query(Some(1), Some(2), Some(Stdout))), [5, 6]);
+        assert_eq!(seqs(&buffer.query(Some(2), Some(5), Some(Stderr))), [4]);
+        assert!(buffer.query(None, Some(0), None).is_empty());
+    }
+
+    #[test]
+    fn levels_are_read_from_json_and_plain_text() {
+        use LogLevel::*;
+        let cases = [
+            (r#"{"level":10,"msg":"x"}"#, Trace),
+            (r#"{"level":20,"msg":"x"}"#, Debug),
+            (r#"{"level":30,"msg":"x"}"#, Info),
+            (r#"{"level":40,"msg":"x"}"#, Warn),
+            (r#"{"level":50,"msg":"x"}"#, Error),
+            (r#"{"level":60,"msg":"x"}"#, Error),
+            (r#"  {"level":"warning"}"#, Warn),
+            (r#"{"level":"fatal"}"#, Error),
+            (r#"{"level":"loud"}"#, Info),
+            (r#"{"msg":"no level"}"#, Info),
+            ("[WARN] [app] disk almost full", Warn),
+            ("[ERROR] [workspace] crashed", Error),
+            ("[DEBUG] [app] tick", Debug),
+            ("TRACE entering", Trace),
+            ("ERR something broke", Error),
+            ("[INFO] [app] HTTP server listening port=1234", Info),
+            ("plain output with no level", Info),
+            ("an error in lowercase is not a level", Info),
+            ("{not json but braces", Info),
+            ("", Info),
+        ];
+        for (line, expected) in cases {
+            assert_eq!(parse_level(line), expected, "{line:?}");
+        }
+    }
+
+    /// Opens `test.log` in a fresh scratch dir; every line the tests write is 10 bytes.
+    fn log_file(name: &str, max_file_bytes: u64, max_files: u32) -> LogFile {
+        let path = testing::scratch_dir(name).join("test.log");
+        LogFile::open(path, file_config(max_file_bytes, max_files)).unwrap()
+    }
+
+    fn file_config(max_file_bytes: u64, max_files: u32) -> LoggingConfig {
+        LoggingConfig {
+            max_file_bytes,
+            max_files,
+            ..LoggingConfig::default()
+        }
+    }
+
+    fn write_lines(file: &mut LogFile, lines: std::ops::RangeInclusive<u32>) {
+        for line in lines {
+            file.append(&format!("line {line:04}\n"));
+        }
+    }
+
+    fn contents(path: &Path) -> Option<String> {
+        fs::read_to_string(path).ok()
+    }
+
+    #[test]
+    fn file_rotates_before_it_would_grow_past_the_limit() {
+        let mut file = log_file("rotate-size", 25, 3);
+        write_lines(&mut file, 1..=2);
+        assert!(!file.rotated_path(1).exists());
+
+        write_lines(&mut file, 3..=3);
+        assert_eq!(contents(&file.path).unwrap(), "line 0003\n");
+        assert_eq!(
+            contents(&file.rotated_path(1)).unwrap(),
+            "line 0001\nline 0002\n"
+        );
+    }
+
+    #[test]
+    fn rotated_files_shift_up_and_the_oldest_is_dropped() {
+        let mut file = log_file("rotate-shift", 25, 2);
+        write_lines(&mut file, 1..=7);
+
+        assert_eq!(contents(&file.path).unwrap(), "line 0007\n");
+        assert_eq!(
+            contents(&file.rotated_path(1)).unwrap(),
+            "line 0005\nline 0006\n"
+        );
+        assert_eq!(
+            contents(&file.rotated_path(2)).unwrap(),
+            "line 0003\nline 0004\n"
+        );
+        assert!(!file.rotated_path(3).exists());
+    }
+
+    #[test]
+    fn file_is_truncated_in_place_when_no_rotated_files_are_kept() {
+        let mut file = log_file("rotate-none", 25, 0);
+        write_lines(&mut file, 1..=3);
+        assert_eq!(contents(&file.path).unwrap(), "line 0003\n");
+        assert!(!file.rotated_path(1).exists());
+    }
+
+    #[test]
+    fn reopened_file_counts_what_is_already_there() {
+        let mut file = log_file("rotate-reopen", 25, 2);
+        write_lines(&mut file, 1..=2);
+        let path = file.path.clone();
+        drop(file);
+
+        let mut file = LogFile::open(path, file_config(25, 2)).unwrap();
+        write_lines(&mut file, 3..=3);
+        assert_eq!(contents(&file.path).unwrap(), "line 0003\n");
+        assert_eq!(
+            contents(&file.rotated_path(1)).unwrap(),
+            "line 0001\nline 0002\n"
+        );
+    }
+
+    #[test]
+    fn an_oversized_line_still_lands_in_an_empty_file() {
+        let mut file = log_file("rotate-oversized", 5, 2);
+        write_lines(&mut file, 1..=1);
+        assert_eq!(contents(&file.path).unwrap(), "line 0001\n");
+        assert!(!file.rotated_path(1).exists());
+    }
+
+    #[test]
+    fn url_fragments_are_redacted() {
+        let cases = [
+            (
+                "loading http://127.0.0.1:9898/#token=abc",
+                "loading http://127.0.0.1:9898/#[REDACTED]",
+            ),
+            (
+                "url=\"https://host/app#secret\" next",
+                "url=\"https://host/app#[REDACTED]\" next",
+            ),
+            (
+                "a http://x/#one b https://y/#two",
+                "a http://x/#[REDACTED] b https://y/#[REDACTED]",
+            ),
+            (
+                "http://x/path?q=1 # not a fragment",
+                "http://x/path?q=1 # not a fragment",
+            ),
+            ("no urls # here", "no urls # here"),
+            ("http://x/#", "http://x/#[REDACTED]"),
+            ("", ""),
+        ];
+        for (input, expected) in cases {
+            assert_eq!(redact_url_fragments(input), expected, "{input:?}");
+        }
+    }
+
+    #[test]
+    fn level_labels_parse_case_insensitively() {
+        assert_eq!(LogLevel::parse(" WARNING "), Some(LogLevel::Warn));
+        assert_eq!(LogLevel::parse("Debug"), Some(LogLevel::Debug));
+        assert_eq!(LogLevel::parse("verbose"), None);
+    }
+}
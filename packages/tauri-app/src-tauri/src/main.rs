@@ -1,13 +1,41 @@
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 
 mod cli_manager;
+mod config;
+mod diagnostics;
+mod handshake;
+mod health;
+mod history;
+mod host;
+mod http_client;
+mod lifecycle;
+mod logs;
+mod metrics;
+mod node;
+mod orphans;
+mod process_tree;
+mod profiles;
+mod remote;
+mod supervisor;
+#[cfg(test)]
+mod testing;
+mod user_shell;
 
-use cli_manager::{CliProcessManager, CliStatus};
+use cli_manager::{CliProcessManager, CliState, CliStatus};
+use history::HistorySnapshot;
+use logs::{LogEntry, LogStream};
+use metrics::ResourceSample;
+use node::RuntimeInfo;
+use orphans::{OrphanAction, OrphanServer};
+use profiles::{ProfileList, ServerProfile};
 use serde_json::json;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 use tauri::menu::{MenuBuilder, MenuItem, SubmenuBuilder};
 use tauri::plugin::{Builder as PluginBuilder, TauriPlugin};
 use tauri::webview::Webview;
 use tauri::{AppHandle, Emitter, Manager, Runtime, Wry};
+use tauri_plugin_dialog::{DialogExt, MessageDialogKind};
 use tauri_plugin_opener::OpenerExt;
 use url::Url;
 
@@ -21,17 +49,144 @@ fn cli_get_status(state: tauri::State<AppState>) -> CliStatus {
     state.manager.status()
 }
 
+/// Waits for the old server to shut down, so it runs off the main thread.
 #[tauri::command]
-fn cli_restart(app: AppHandle, state: tauri::State<AppState>) -> Result<CliStatus, String> {
+async fn cli_restart(
+    app: AppHandle,
+    state: tauri::State<'_, AppState>,
+) -> Result<CliStatus, String> {
     let dev_mode = is_dev_mode();
     state.manager.stop().map_err(|e| e.to_string())?;
     state
         .manager
-        .start(app, dev_mode)
+        .restart(Arc::new(app), dev_mode)
         .map_err(|e| e.to_string())?;
     Ok(state.manager.status())
 }
 
+#[tauri::command]
+fn cli_get_history(state: tauri::State<AppState>) -> HistorySnapshot {
+    state.manager.history()
+}
+
+#[tauri::command]
+fn cli_get_metrics(state: tauri::State<AppState>, limit: Option<usize>) -> Vec<ResourceSample> {
+    state.manager.metrics(limit)
+}
+
+#[tauri::command]
+fn cli_get_logs(
+    state: tauri::State<AppState>,
+    since: Option<u64>,
+    limit: Option<usize>,
+    stream: Option<LogStream>,
+) -> Vec<LogEntry> {
+    state.manager.logs(since, limit, stream)
+}
+
+/// Probes every Node.js install it can find, so it runs off the main thread.
+#[tauri::command]
+async fn cli_get_runtime_info() -> RuntimeInfo {
+    node::discover()
+}
+
+#[tauri::command]
+fn cli_get_log_path() -> Option<String> {
+    logs::log_file_path().map(|path| path.display().to_string())
+}
+
+/// Returns where the bundle was written, or `None` if the user cancelled.
+#[tauri::command]
+async fn diagnostics_export(
+    app: AppHandle,
+    state: tauri::State<'_, AppState>,
+) -> Result<Option<String>, String> {
+    let path = diagnostics::export_with_dialog(&app, &state.manager, is_dev_mode())?;
+    Ok(path.map(|path| path.display().to_string()))
+}
+
+#[tauri::command]
+fn cli_get_orphans(state: tauri::State<AppState>) -> Vec<OrphanServer> {
+    state.manager.orphans()
+}
+
+/// Reaping waits for the orphan to exit, so it runs off the main thread.
+#[tauri::command]
+async fn cli_resolve_orphan(
+    app: AppHandle,
+    state: tauri::State<'_, AppState>,
+    pid: u32,
+    action: OrphanAction,
+) -> Result<CliStatus, String> {
+    let dev_mode = is_dev_mode();
+    match action {
+        OrphanAction::Adopt => state
+            .manager
+            .adopt_orphan(Arc::new(app), dev_mode, pid)
+            .map_err(|e| e.to_string())?,
+        OrphanAction::Reap => {
+            state.manager.reap_orphan(pid).map_err(|e| e.to_string())?;
+            // Startup was held back until every orphan was dealt with.
+            let idle = state.manager.status().state == CliState::Stopped;
+            if idle && state.manager.orphans().is_empty() {
+                state
+                    .manager
+                    .start(Arc::new(app), dev_mode)
+                    .map_err(|e| e.to_string())?;
+            }
+        }
+    }
+    Ok(state.manager.status())
+}
+
+#[tauri::command]
+fn profiles_list() -> ProfileList {
+    profiles::list()
+}
+
+#[tauri::command]
+fn profiles_save(profile: ServerProfile) -> Result<ProfileList, String> {
+    profiles::save(profile)
+}
+
+/// Stops the current server before switching, so it runs off the main thread.
+#[tauri::command]
+async fn profiles_connect(
+    app: AppHandle,
+    state: tauri::State<'_, AppState>,
+    id: String,
+) -> Result<CliStatus, String> {
+    let profile = profiles::set_active(&id)?;
+    log::info!(profile = profile.id.as_str(); "switching server profile");
+    state
+        .manager
+        .start(Arc::new(app), is_dev_mode())
+        .map_err(|e| e.to_string())?;
+    Ok(state.manager.status())
+}
+
+/// Set once the server is down, so the exit requested afterwards goes through.
+static SERVER_STOPPED: AtomicBool = AtomicBool::new(false);
+/// Set by the first exit request, so repeated ones don't stop the server twice.
+static EXIT_STARTED: AtomicBool = AtomicBool::new(false);
+
+/// Stops the server off the main thread, then exits with `code`.
+fn stop_server_then_exit(app: &AppHandle, code: i32) {
+    if EXIT_STARTED.swap(true, Ordering::SeqCst) {
+        return;
+    }
+    let app = app.clone();
+    std::thread::spawn(move || {
+        if let Some(state) = app.try_state::<AppState>() {
+            if let Err(err) = state.manager.stop() {
+                log::warn!(error:% = err; "failed to stop server before exit");
+            }
+        }
+        SERVER_STOPPED.store(true, Ordering::SeqCst);
+        app.exit(code);
+    });
+}
+
 fn is_dev_mode() -> bool {
     cfg!(debug_assertions) || std::env::var("TAURI_DEV").is_ok()
 }
@@ -39,7 +194,13 @@ fn is_dev_mode() -> bool {
 fn should_allow_internal(url: &Url) -> bool {
     match url.scheme() {
         "tauri" | "asset" | "file" => true,
-        "http" | "https" => matches!(url.host_str(), Some("127.0.0.1" | "localhost")),
+        "http" | "https" => {
+            // `tauri.localhost` serves the app's own pages on Windows.
+            matches!(
+                url.host_str(),
+                Some("127.0.0.1" | "[::1]" | "localhost" | "tauri.localhost")
+            ) || remote::is_allowed(url)
+        }
         _ => false,
     }
 }
@@ -54,14 +215,15 @@ fn intercept_navigation<R: Runtime>(webview: &Webview<R>, url: &Url) -> bool {
         .opener()
         .open_url(url.as_str(), None::<&str>)
     {
-        eprintln!("[tauri] failed to open external link {}: {}", url, err);
+        log::warn!(url:% = url, error:% = err; "failed to open external link");
     }
     false
 }
 
 fn main() {
+    logs::init(is_dev_mode());
     let navigation_guard: TauriPlugin<Wry, ()> = PluginBuilder::new("external-link-guard")
-        .on_navigation(|webview, url| intercept_navigation(webview, url))
+        .on_navigation(intercept_navigation)
         .build();
 
     tauri::Builder::default()
@@ -72,18 +234,49 @@ fn main() {
             manager: CliProcessManager::new(),
         })
         .setup(|app| {
-            build_menu(&app.handle())?;
+            match app.path().app_log_dir() {
+                Ok(dir) => {
+                    match logs::init_log_file(&dir, &config::load_desktop_config().logging) {
+                        Ok(path) => log::info!(path:% = path.display(); "writing logs to file"),
+                        Err(err) => log::warn!(error:% = err; "failed to open log file"),
+                    }
+                }
+                Err(err) => log::warn!(error:% = err; "no log directory available"),
+            }
+            build_menu(app.handle())?;
             let dev_mode = is_dev_mode();
             let app_handle = app.handle().clone();
             let manager = app.state::<AppState>().manager.clone();
             std::thread::spawn(move || {
-                if let Err(err) = manager.start(app_handle.clone(), dev_mode) {
+                // A new server would reap the orphans' OpenCode instances, so let the
+                // user decide what happens to them first.
+                let orphans = manager.orphans();
+                if !orphans.is_empty() {
+                    log::info!(count = orphans.len(); "found orphaned servers");
+                    let _ = app_handle.emit("cli:orphans", orphans);
+                    return;
+                }
+                if let Err(err) = manager.start(Arc::new(app_handle.clone()), dev_mode) {
                     let _ = app_handle.emit("cli:error", json!({"message": err.to_string()}));
                 }
             });
             Ok(())
         })
-        .invoke_handler(tauri::generate_handler![cli_get_status, cli_restart])
+        .invoke_handler(tauri::generate_handler![
+            cli_get_status,
+            cli_restart,
+            cli_get_history,
+            cli_get_logs,
+            cli_get_metrics,
+            cli_get_runtime_info,
+            cli_get_log_path,
+            cli_get_orphans,
+            cli_resolve_orphan,
+            profiles_list,
+            profiles_save,
+            profiles_connect,
+            diagnostics_export
+        ])
         .on_menu_event(|app_handle, event| {
             match event.id().0.as_str() {
                 // File menu
@@ -136,10 +329,28 @@ fn main() {
                     }
                 }
 
+                // Help menu
+                "export_diagnostics" => {
+                    let app = app_handle.clone();
+                    std::thread::spawn(move || {
+                        let state = app.state::<AppState>();
+                        if let Err(err) =
+                            diagnostics::export_with_dialog(&app, &state.manager, is_dev_mode())
+                        {
+                            log::error!(error:% = err; "diagnostics export failed");
+                            app.dialog()
+                                .message(err)
+                                .title("Export diagnostics failed")
+                                .kind(MessageDialogKind::Error)
+                                .blocking_show();
+                        }
+                    });
+                }
+
                 // App menu (macOS)
                 "about" => {
                     // TODO: Implement about dialog
-                    println!("About menu item clicked");
+                    log::debug!("About menu item clicked");
                 }
                 "hide" => {
                     if let Some(window) = app_handle.get_webview_window("main") {
@@ -148,43 +359,33 @@ fn main() {
                 }
                 "hide_others" => {
                     // TODO: Hide other app windows
-                    println!("Hide Others menu item clicked");
+                    log::debug!("Hide Others menu item clicked");
                 }
                 "show_all" => {
                     // TODO: Show all app windows
-                    println!("Show All menu item clicked");
+                    log::debug!("Show All menu item clicked");
                 }
 
                 _ => {
-                    println!("Unhandled menu event: {}", event.id().0);
+                    log::debug!(id = event.id().0.as_str(); "unhandled menu event");
                 }
             }
         })
         .build(tauri::generate_context!())
         .expect("error while building tauri application")
         .run(|app_handle, event| match event {
-            tauri::RunEvent::ExitRequested { .. } => {
-                let app = app_handle.clone();
-                std::thread::spawn(move || {
-                    if let Some(state) = app.try_state::<AppState>() {
-                        let _ = state.manager.stop();
-                    }
-                    app.exit(0);
-                });
+            // Hold the exit until the server has saved its state and gone away.
+            tauri::RunEvent::ExitRequested { code, api, .. }
+                if !SERVER_STOPPED.load(Ordering::SeqCst) =>
+            {
+                api.prevent_exit();
+                stop_server_then_exit(app_handle, code.unwrap_or(0));
             }
             tauri::RunEvent::WindowEvent {
                 event: tauri::WindowEvent::Destroyed,
                 ..
-            } => {
-                if app_handle.webview_windows().len() <= 1 {
-                    let app = app_handle.clone();
-                    std::thread::spawn(move || {
-                        if let Some(state) = app.try_state::<AppState>() {
-                            let _ = state.manager.stop();
-                        }
-                        app.exit(0);
-                    });
-                }
+            } if app_handle.webview_windows().len() <= 1 => {
+                stop_server_then_exit(app_handle, 0);
             }
             _ => {}
         });
@@ -260,6 +461,12 @@ fn build_menu(app: &AppHandle) -> tauri::Result<()> {
         .build()?;
     submenus.push(window_menu);
 
+    // Help menu
+    let help_menu = SubmenuBuilder::new(app, "Help")
+        .text("export_diagnostics", "Export Diagnostics…")
+        .build()?;
+    submenus.push(help_menu);
+
     // Build the main menu with all submenus
     let submenu_refs: Vec<&dyn tauri::menu::IsMenuItem<_>> = submenus
         .iter()
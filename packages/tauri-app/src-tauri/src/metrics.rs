@@ -0,0 +1,402 @@
+use crate::config::MetricsConfig;
+use serde::Serialize;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::time::Instant;
+
+/// How many samples `cli_get_metrics` can return.
+const MAX_SAMPLES: usize = 120;
+
+/// Resource usage of the server and everything it spawned, summed over the tree.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ResourceSample {
+    /// Unix time in milliseconds.
+    pub timestamp: u64,
+    pub pid: u32,
+    pub process_count: usize,
+    /// Since the previous sample, relative to one core, so a busy tree can exceed 100.
+    pub cpu_percent: f64,
+    pub rss_bytes: u64,
+}
+
+/// Cumulative CPU time and current resident memory of one process.
+#[derive(Debug, Clone, Copy, Default)]
+struct Usage {
+    cpu_ns: u64,
+    rss_bytes: u64,
+}
+
+/// Turns cumulative CPU times into percentages by remembering the previous reading.
+#[derive(Debug, Default)]
+pub struct MetricsSampler {
+    previous: Option<(Instant, u64)>,
+}
+
+impl MetricsSampler {
+    /// `None` once `root` has exited.
+    pub fn sample(&mut self, root: u32, timestamp: u64) -> Option<ResourceSample> {
+        let pids = descendants(root);
+        if pids.is_empty() {
+            return None;
+        }
+        let total = pids.iter().filter_map(|&pid| platform::usage(pid)).fold(
+            Usage::default(),
+            |sum, usage| Usage {
+                cpu_ns: sum.cpu_ns + usage.cpu_ns,
+                rss_bytes: sum.rss_bytes + usage.rss_bytes,
+            },
+        );
+
+        let now = Instant::now();
+        // Exited children take their CPU time with them, so the total can go down.
+        let cpu_percent = match self.previous {
+            Some((at, cpu_ns)) if total.cpu_ns >= cpu_ns => {
+                let wall_ns = now.duration_since(at).as_nanos().max(1) as f64;
+                (total.cpu_ns - cpu_ns) as f64 / wall_ns * 100.0
+            }
+            _ => 0.0,
+        };
+        self.previous = Some((now, total.cpu_ns));
+
+        Some(ResourceSample {
+            timestamp,
+            pid: root,
+            process_count: pids.len(),
+            cpu_percent,
+            rss_bytes: total.rss_bytes,
+        })
+    }
+}
+
+/// Recent samples, oldest dropped first.
+#[derive(Debug, Default)]
+pub struct MetricsBuffer {
+    samples: VecDeque<ResourceSample>,
+}
+
+impl MetricsBuffer {
+    pub fn push(&mut self, sample: ResourceSample) {
+        if self.samples.len() == MAX_SAMPLES {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(sample);
+    }
+
+    pub fn recent(&self, limit: Option<usize>) -> Vec<ResourceSample> {
+        let skip = self
+            .samples
+            .len()
+            .saturating_sub(limit.unwrap_or(MAX_SAMPLES));
+        self.samples.iter().skip(skip).cloned().collect()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Resource {
+    Memory,
+    Cpu,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AlertAction {
+    Warn,
+    Restart,
+}
+
+/// A threshold from `desktop.json` that the server has stayed above for long enough.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ResourceAlert {
+    pub resource: Resource,
+    pub action: AlertAction,
+    pub value: f64,
+    pub threshold: f64,
+}
+
+/// Counts consecutive samples above each threshold. An alert fires once when the count
+/// reaches `sustainedSamples` and again only after usage has dropped back below.
+#[derive(Debug)]
+pub struct ThresholdTracker {
+    config: MetricsConfig,
+    memory_warn: u32,
+    memory_restart: u32,
+    cpu_warn: u32,
+}
+
+impl ThresholdTracker {
+    pub fn new(config: MetricsConfig) -> Self {
+        Self {
+            config,
+            memory_warn: 0,
+            memory_restart: 0,
+            cpu_warn: 0,
+        }
+    }
+
+    pub fn record(&mut self, sample: &ResourceSample) -> Vec<ResourceAlert> {
+        let rss_mb = sample.rss_bytes as f64 / (1024.0 * 1024.0);
+        let sustained = self.config.sustained_samples.max(1);
+        let checks = [
+            (
+                &mut self.memory_restart,
+                Resource::Memory,
+                AlertAction::Restart,
+                rss_mb,
+                self.config.restart_rss_mb as f64,
+            ),
+            (
+                &mut self.memory_warn,
+                Resource::Memory,
+                AlertAction::Warn,
+                rss_mb,
+                self.config.warn_rss_mb as f64,
+            ),
+            (
+                &mut self.cpu_warn,
+                Resource::Cpu,
+                AlertAction::Warn,
+                sample.cpu_percent,
+                self.config.warn_cpu_percent,
+            ),
+        ];
+
+        let mut alerts = Vec::new();
+        for (count, resource, action, value, threshold) in checks {
+            // A threshold of zero disables the check.
+            if threshold <= 0.0 || value <= threshold {
+                *count = 0;
+                continue;
+            }
+            *count += 1;
+            if *count == sustained {
+                alerts.push(ResourceAlert {
+                    resource,
+                    action,
+                    value,
+                    threshold,
+                });
+            }
+        }
+        alerts
+    }
+}
+
+/// `root` and all of its live descendants; empty if `root` itself is gone.
+fn descendants(root: u32) -> Vec<u32> {
+    let mut children: HashMap<u32, Vec<u32>> = HashMap::new();
+    let mut root_alive = false;
+    for (pid, ppid) in platform::parents() {
+        root_alive |= pid == root;
+        if pid != ppid {
+            children.entry(ppid).or_default().push(pid);
+        }
+    }
+    if !root_alive {
+        return Vec::new();
+    }
+
+    let mut seen = HashSet::from([root]);
+    let mut tree = vec![root];
+    let mut index = 0;
+    while index < tree.len() {
+        for &child in children.get(&tree[index]).into_iter().flatten() {
+            if seen.insert(child) {
+                tree.push(child);
+            }
+        }
+        index += 1;
+    }
+    tree
+}
+
+#[cfg(target_os = "linux")]
+mod platform {
+    use super::Usage;
+    use once_cell::sync::Lazy;
+    use std::fs;
+
+    static CLOCK_TICKS: Lazy<u64> =
+        Lazy::new(|| unsafe { libc::sysconf(libc::_SC_CLK_TCK) }.max(1) as u64);
+    static PAGE_SIZE: Lazy<u64> =
+        Lazy::new(|| unsafe { libc::sysconf(libc::_SC_PAGESIZE) }.max(1) as u64);
+
+    /// Fields of `/proc/<pid>/stat` that follow the command name, which may itself
+    /// contain spaces and parentheses.
+    fn stat_fields(pid: u32) -> Option<Vec<String>> {
+        let stat = fs::read_to_string(format!("/proc/{pid}/stat")).ok()?;
+        let rest = &stat[stat.rfind(')')? + 1..];
+        Some(rest.split_whitespace().map(str::to_string).collect())
+    }
+
+    pub fn parents() -> Vec<(u32, u32)> {
+        let Ok(entries) = fs::read_dir("/proc") else {
+            return Vec::new();
+        };
+        entries
+            .flatten()
+            .filter_map(|entry| entry.file_name().to_str()?.parse::<u32>().ok())
+            .filter_map(|pid| {
+                let ppid = stat_fields(pid)?.get(1)?.parse().ok()?;
+                Some((pid, ppid))
+            })
+            .collect()
+    }
+
+    pub fn usage(pid: u32) -> Option<Usage> {
+        // Counted from the state field: utime is 11, stime 12, rss (in pages) 21.
+        let fields = stat_fields(pid)?;
+        let field = |index: usize| fields.get(index)?.parse::<u64>().ok();
+        let ticks = field(11)? + field(12)?;
+        Some(Usage {
+            cpu_ns: ticks * 1_000_000_000 / *CLOCK_TICKS,
+            rss_bytes: field(21)? * *PAGE_SIZE,
+        })
+    }
+}
+
+#[cfg(target_os = "macos")]
+mod platform {
+    use super::Usage;
+    use once_cell::sync::Lazy;
+    use std::ffi::c_void;
+    use std::mem;
+
+    /// `proc_taskinfo` reports CPU time in Mach ticks, which are not nanoseconds on
+    /// Apple Silicon.
+    #[allow(deprecated)]
+    static TIMEBASE: Lazy<(u64, u64)> = Lazy::new(|| {
+        let mut info = libc::mach_timebase_info { numer: 0, denom: 0 };
+        unsafe { libc::mach_timebase_info(&mut info) };
+        if info.denom == 0 {
+            (1, 1)
+        } else {
+            (u64::from(info.numer), u64::from(info.denom))
+        }
+    });
+
+    fn pid_info<T>(pid: u32, flavor: libc::c_int) -> Option<T> {
+        let mut info: T = unsafe { mem::zeroed() };
+        let size = mem::size_of::<T>() as libc::c_int;
+        let written = unsafe {
+            libc::proc_pidinfo(
+                pid as libc::c_int,
+                flavor,
+                0,
+                &mut info as *mut T as *mut c_void,
+                size,
+            )
+        };
+        (written == size).then_some(info)
+    }
+
+    pub fn parents() -> Vec<(u32, u32)> {
+        let count = unsafe { libc::proc_listallpids(std::ptr::null_mut(), 0) };
+        if count <= 0 {
+            return Vec::new();
+        }
+        // Leave room for processes started in between the two calls.
+        let mut pids = vec![0 as libc::pid_t; count as usize + 64];
+        let bytes = (pids.len() * mem::size_of::<libc::pid_t>()) as libc::c_int;
+        let count = unsafe { libc::proc_listallpids(pids.as_mut_ptr() as *mut c_void, bytes) };
+        pids.truncate(count.max(0) as usize);
+
+        pids.into_iter()
+            .filter(|&pid| pid > 0)
+            .filter_map(|pid| {
+                let info: libc::proc_bsdinfo = pid_info(pid as u32, libc::PROC_PIDTBSDINFO)?;
+                Some((pid as u32, info.pbi_ppid))
+            })
+            .collect()
+    }
+
+    pub fn usage(pid: u32) -> Option<Usage> {
+        let info: libc::proc_taskinfo = pid_info(pid, libc::PROC_PIDTASKINFO)?;
+        let (numer, denom) = *TIMEBASE;
+        let ticks = info.pti_total_user + info.pti_total_system;
+        Some(Usage {
+            cpu_ns: (u128::from(ticks) * u128::from(numer) / u128::from(denom)) as u64,
+            rss_bytes: info.pti_resident_size,
+        })
+    }
+}
+
+#[cfg(windows)]
+mod platform {
+    use super::Usage;
+    use std::mem;
+    use windows_sys::Win32::Foundation::{CloseHandle, FILETIME, INVALID_HANDLE_VALUE};
+    use windows_sys::Win32::System::Diagnostics::ToolHelp::{
+        CreateToolhelp32Snapshot, Process32FirstW, Process32NextW, PROCESSENTRY32W,
+        TH32CS_SNAPPROCESS,
+    };
+    use windows_sys::Win32::System::ProcessStatus::{
+        K32GetProcessMemoryInfo, PROCESS_MEMORY_COUNTERS,
+    };
+    use windows_sys::Win32::System::Threading::{
+        GetProcessTimes, OpenProcess, PROCESS_QUERY_LIMITED_INFORMATION,
+    };
+
+    pub fn parents() -> Vec<(u32, u32)> {
+        let mut parents = Vec::new();
+        unsafe {
+            let snapshot = CreateToolhelp32Snapshot(TH32CS_SNAPPROCESS, 0);
+            if snapshot == INVALID_HANDLE_VALUE {
+                return parents;
+            }
+            let mut entry: PROCESSENTRY32W = mem::zeroed();
+            entry.dwSize = mem::size_of::<PROCESSENTRY32W>() as u32;
+            let mut more = Process32FirstW(snapshot, &mut entry) != 0;
+            while more {
+                parents.push((entry.th32ProcessID, entry.th32ParentProcessID));
+                more = Process32NextW(snapshot, &mut entry) != 0;
+            }
+            CloseHandle(snapshot);
+        }
+        parents
+    }
+
+    fn filetime_ns(time: &FILETIME) -> u64 {
+        // FILETIME counts 100ns intervals.
+        ((u64::from(time.dwHighDateTime) << 32) | u64::from(time.dwLowDateTime)) * 100
+    }
+
+    pub fn usage(pid: u32) -> Option<Usage> {
+        unsafe {
+            let handle = OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION, 0, pid);
+            if handle.is_null() {
+                return None;
+            }
+            let mut created: FILETIME = mem::zeroed();
+            let mut exited: FILETIME = mem::zeroed();
+            let mut kernel: FILETIME = mem::zeroed();
+            let mut user: FILETIME = mem::zeroed();
+            let mut memory: PROCESS_MEMORY_COUNTERS = mem::zeroed();
+            memory.cb = mem::size_of::<PROCESS_MEMORY_COUNTERS>() as u32;
+
+            let usage =
+                (GetProcessTimes(handle, &mut created, &mut exited, &mut kernel, &mut user) != 0
+                    && K32GetProcessMemoryInfo(handle, &mut memory, memory.cb) != 0)
+                    .then(|| Usage {
+                        cpu_ns: filetime_ns(&kernel) + filetime_ns(&user),
+                        rss_bytes: memory.WorkingSetSize as u64,
+                    });
+            CloseHandle(handle);
+            usage
+        }
+    }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
+mod platform {
+    use super::Usage;
+
+    pub fn parents() -> Vec<(u32, u32)> {
+        Vec::new()
+    }
+
+    pub fn usage(_pid: u32) -> Option<Usage> {
+        None
+    }
+}
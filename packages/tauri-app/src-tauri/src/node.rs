@@ -0,0 +1,363 @@
+use crate::config::{load_desktop_config, NodeRuntime};
+use crate::user_shell;
+use serde::Serialize;
+use std::collections::HashSet;
+use std::env;
+use std::ffi::OsString;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+/// Oldest Node.js major the server runs on; keep in sync with `engines.node` in package.json.
+pub const MIN_NODE_MAJOR: u32 = 18;
+
+/// Target the app was built for; bundled runtimes live under `resources/node/<triple>/`.
+pub const TARGET_TRIPLE: &str = env!("AGROFORGE_TARGET_TRIPLE");
+
+#[cfg(windows)]
+const NODE_EXE: &str = "node.exe";
+#[cfg(not(windows))]
+const NODE_EXE: &str = "node";
+
+/// Where a candidate binary was found.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum NodeSource {
+    /// The `NODE_BINARY` environment variable.
+    Env,
+    /// Shipped in the app bundle by `scripts/prebuild.js`.
+    Bundled,
+    Path,
+    /// Well-known install prefixes a GUI app's `PATH` often lacks, such as Homebrew's.
+    System,
+    Volta,
+    Fnm,
+    Nvm,
+    Asdf,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct NodeVersion {
+    pub major: u32,
+    pub minor: u32,
+    pub patch: u32,
+}
+
+impl NodeVersion {
+    /// Parses `node --version` output such as `v20.11.1`.
+    pub fn parse(text: &str) -> Option<Self> {
+        let mut parts = text.trim().trim_start_matches('v').splitn(3, '.');
+        let major = parts.next()?.parse().ok()?;
+        let minor = parts.next().map_or(Some(0), |part| part.parse().ok())?;
+        let patch = parts
+            .next()
+            .map_or(Some(0), |part| part.split('-').next()?.parse().ok())?;
+        Some(Self {
+            major,
+            minor,
+            patch,
+        })
+    }
+}
+
+impl Serialize for NodeVersion {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl fmt::Display for NodeVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "v{}.{}.{}", self.major, self.minor, self.patch)
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NodeCandidate {
+    pub path: String,
+    pub source: NodeSource,
+    pub version: Option<NodeVersion>,
+    /// Why the candidate cannot be used, if it cannot.
+    pub problem: Option<String>,
+}
+
+impl NodeCandidate {
+    fn probe(path: PathBuf, source: NodeSource) -> Self {
+        let (version, problem) = match node_version(&path) {
+            Ok(version) if version.major >= MIN_NODE_MAJOR => (Some(version), None),
+            Ok(version) => (
+                Some(version),
+                Some(format!("{version} is older than v{MIN_NODE_MAJOR}")),
+            ),
+            Err(problem) => (None, Some(problem)),
+        };
+        Self {
+            path: path.to_string_lossy().into_owned(),
+            source,
+            version,
+            problem,
+        }
+    }
+
+    pub fn is_usable(&self) -> bool {
+        self.problem.is_none()
+    }
+}
+
+/// Every Node.js binary found, and the one the server would be started with.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RuntimeInfo {
+    pub preference: NodeRuntime,
+    pub selected: Option<NodeCandidate>,
+    pub candidates: Vec<NodeCandidate>,
+    pub minimum_major: u32,
+    /// Version pinned by the nearest `.nvmrc` or `.node-version`, if any.
+    pub requested: Option<String>,
+    /// What to tell the user when nothing usable was found.
+    pub error: Option<String>,
+}
+
+/// Looks for Node.js in the app bundle and wherever a shell user would have installed it,
+/// as allowed by `node.runtime` in `desktop.json`. `NODE_BINARY`, when set, is the only
+/// candidate considered.
+pub fn discover() -> RuntimeInfo {
+    let preference = load_desktop_config().node.runtime;
+    let requested = requested_version();
+
+    if let Some(path) = env::var_os("NODE_BINARY").filter(|value| !value.is_empty()) {
+        let path = PathBuf::from(path);
+        let path = which::which(&path).unwrap_or(path);
+        let candidate = NodeCandidate::probe(path, NodeSource::Env);
+        let error = candidate.problem.as_ref().map(|problem| {
+            format!(
+                "NODE_BINARY is set to {}, but {problem}. Point it at Node.js {MIN_NODE_MAJOR} or newer, or unset it.",
+                candidate.path
+            )
+        });
+        return RuntimeInfo {
+            preference,
+            selected: candidate.is_usable().then(|| candidate.clone()),
+            candidates: vec![candidate],
+            minimum_major: MIN_NODE_MAJOR,
+            requested,
+            error,
+        };
+    }
+
+    let mut paths = Vec::new();
+    if preference != NodeRuntime::System {
+        paths.extend(bundled_node().map(|path| (path, NodeSource::Bundled)));
+    }
+    if preference != NodeRuntime::Bundled {
+        paths.extend(candidate_paths());
+    }
+
+    let mut seen = HashSet::new();
+    let candidates: Vec<NodeCandidate> = paths
+        .into_iter()
+        .filter(|(path, _)| path.is_file())
+        .filter(|(path, _)| seen.insert(fs::canonicalize(path).unwrap_or_else(|_| path.clone())))
+        .map(|(path, source)| NodeCandidate::probe(path, source))
+        .collect();
+
+    // A usable bundled runtime wins; a project's pin only chooses among installed ones.
+    let bundled = candidates
+        .iter()
+        .find(|candidate| candidate.source == NodeSource::Bundled);
+    let pinned = requested.as_deref().and_then(NodeVersion::parse);
+    let selected = bundled
+        .filter(|candidate| candidate.is_usable())
+        .or_else(|| {
+            pinned.and_then(|pinned| {
+                candidates
+                    .iter()
+                    .find(|candidate| candidate.is_usable() && matches_pin(candidate, pinned))
+            })
+        })
+        .or_else(|| candidates.iter().find(|candidate| candidate.is_usable()))
+        .cloned();
+
+    let error = selected.is_none().then(|| {
+        if preference == NodeRuntime::Bundled {
+            return match bundled {
+                Some(candidate) => format!(
+                    "The bundled Node.js runtime at {} cannot be used: {}.",
+                    candidate.path,
+                    candidate.problem.as_deref().unwrap_or_default()
+                ),
+                None => format!(
+                    "This build has no bundled Node.js runtime for {TARGET_TRIPLE}. Set node.runtime to \"system\" in desktop.json to use an installed one."
+                ),
+            };
+        }
+        let newest = candidates
+            .iter()
+            .filter(|candidate| candidate.version.is_some())
+            .max_by_key(|candidate| candidate.version);
+        match newest {
+            Some(newest) => format!(
+                "Node.js {MIN_NODE_MAJOR} or newer is required, but the newest one found is {} at {}. Install a newer Node.js or set NODE_BINARY to one.",
+                newest.version.map(|version| version.to_string()).unwrap_or_default(),
+                newest.path
+            ),
+            None => format!(
+                "Node.js was not found. Install Node.js {MIN_NODE_MAJOR} or newer, or set NODE_BINARY to its path."
+            ),
+        }
+    });
+
+    RuntimeInfo {
+        preference,
+        selected,
+        candidates,
+        minimum_major: MIN_NODE_MAJOR,
+        requested,
+        error,
+    }
+}
+
+/// A pin of `18` accepts any 18.x; `18.17` any 18.17.x.
+fn matches_pin(candidate: &NodeCandidate, pinned: NodeVersion) -> bool {
+    let Some(version) = candidate.version else {
+        return false;
+    };
+    version.major == pinned.major
+        && (pinned.minor == 0 || version.minor == pinned.minor)
+        && (pinned.patch == 0 || version.patch == pinned.patch)
+}
+
+fn node_version(path: &Path) -> Result<NodeVersion, String> {
+    let output = Command::new(path)
+        .arg("--version")
+        .stdin(Stdio::null())
+        .output()
+        .map_err(|err| format!("it could not be run: {err}"))?;
+    if !output.status.success() {
+        return Err(format!("`--version` failed with {}", output.status));
+    }
+    let text = String::from_utf8_lossy(&output.stdout);
+    NodeVersion::parse(&text)
+        .ok_or_else(|| format!("it reported an unknown version {:?}", text.trim()))
+}
+
+/// The runtime `prebuild.js` copied into the bundle for this target, if this build has one.
+fn bundled_node() -> Option<PathBuf> {
+    let exe = env::current_exe().ok()?;
+    let dir = exe.parent()?;
+    // macOS keeps resources in Contents/Resources, the Linux packages under lib/, and
+    // Windows next to the executable.
+    [
+        dir.join("../Resources"),
+        dir.join("../lib/AgroForge"),
+        dir.join("../lib/agroforge"),
+        dir.to_path_buf(),
+    ]
+    .iter()
+    .map(|root| {
+        root.join("resources/node")
+            .join(TARGET_TRIPLE)
+            .join(NODE_EXE)
+    })
+    .find(|path| path.is_file())
+}
+
+/// Installed candidates in order of preference. Version manager installs come newest first.
+fn candidate_paths() -> Vec<(PathBuf, NodeSource)> {
+    // The login shell's PATH is what a terminal user would get; a GUI app's own is often
+    // much shorter.
+    let search_path = user_shell::login_env()
+        .and_then(|env| env.get("PATH"))
+        .map(OsString::from)
+        .or_else(|| env::var_os("PATH"));
+    let cwd = env::current_dir().unwrap_or_default();
+    let mut paths: Vec<(PathBuf, NodeSource)> = which::which_in_all(NODE_EXE, search_path, cwd)
+        .map(|found| found.map(|path| (path, NodeSource::Path)).collect())
+        .unwrap_or_default();
+
+    #[cfg(unix)]
+    for dir in ["/opt/homebrew/bin", "/usr/local/bin", "/usr/bin"] {
+        paths.push((Path::new(dir).join(NODE_EXE), NodeSource::System));
+    }
+
+    let home = dirs::home_dir();
+    let dir_from = |var: &str, fallback: &str| {
+        env::var_os(var)
+            .map(PathBuf::from)
+            .or_else(|| home.as_ref().map(|home| home.join(fallback)))
+    };
+
+    if let Some(volta) = dir_from("VOLTA_HOME", ".volta") {
+        paths.push((volta.join("bin").join(NODE_EXE), NodeSource::Volta));
+        paths.extend(installs(&volta.join("tools/image/node"), NodeSource::Volta));
+    }
+    let fnm_dirs = [
+        dir_from("FNM_DIR", ".fnm"),
+        dirs::data_dir().map(|dir| dir.join("fnm")),
+    ];
+    for fnm in fnm_dirs.into_iter().flatten() {
+        let versions = fnm.join("node-versions");
+        paths.extend(
+            versioned_dirs(&versions)
+                .into_iter()
+                .map(|dir| (node_in(&dir.join("installation")), NodeSource::Fnm)),
+        );
+    }
+    if let Some(nvm) = dir_from("NVM_DIR", ".nvm") {
+        paths.extend(installs(&nvm.join("versions/node"), NodeSource::Nvm));
+    }
+    // nvm-windows keeps each version directly under NVM_HOME.
+    if let Some(nvm_home) = env::var_os("NVM_HOME").map(PathBuf::from) {
+        paths.extend(installs(&nvm_home, NodeSource::Nvm));
+    }
+    if let Some(asdf) = dir_from("ASDF_DATA_DIR", ".asdf") {
+        paths.extend(installs(&asdf.join("installs/nodejs"), NodeSource::Asdf));
+    }
+
+    paths
+}
+
+fn installs(root: &Path, source: NodeSource) -> Vec<(PathBuf, NodeSource)> {
+    versioned_dirs(root)
+        .into_iter()
+        .map(|dir| (node_in(&dir), source))
+        .collect()
+}
+
+/// Subdirectories named after a version, newest first.
+fn versioned_dirs(root: &Path) -> Vec<PathBuf> {
+    let Ok(entries) = fs::read_dir(root) else {
+        return Vec::new();
+    };
+    let mut dirs: Vec<(NodeVersion, PathBuf)> = entries
+        .flatten()
+        .filter_map(|entry| {
+            let version = NodeVersion::parse(&entry.file_name().to_string_lossy())?;
+            Some((version, entry.path()))
+        })
+        .collect();
+    dirs.sort_by_key(|(version, _)| std::cmp::Reverse(*version));
+    dirs.into_iter().map(|(_, dir)| dir).collect()
+}
+
+fn node_in(install: &Path) -> PathBuf {
+    if cfg!(windows) {
+        install.join(NODE_EXE)
+    } else {
+        install.join("bin").join(NODE_EXE)
+    }
+}
+
+/// Contents of the nearest `.nvmrc` or `.node-version` above the working directory.
+fn requested_version() -> Option<String> {
+    let cwd = env::current_dir().ok()?;
+    cwd.ancestors().find_map(|dir| {
+        [".nvmrc", ".node-version"].iter().find_map(|name| {
+            let content = fs::read_to_string(dir.join(name)).ok()?;
+            let version = content.lines().next()?.trim();
+            (!version.is_empty()).then(|| version.to_string())
+        })
+    })
+}
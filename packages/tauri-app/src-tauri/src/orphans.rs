@@ -0,0 +1,139 @@
+use crate::config::{resolve_pid_dir, write_private_file};
+use crate::process_tree::is_alive;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+const LOCK_PREFIX: &str = "desktop-";
+const LOCK_SUFFIX: &str = ".json";
+
+/// What a running desktop app records about the server it spawned, so the next launch can
+/// find it again if the app dies without stopping it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ServerLock {
+    pub app_pid: u32,
+    pub server_pid: u32,
+    /// Server entry script, used to make sure a live pid still belongs to our server.
+    pub entry: String,
+    pub started_at: u64,
+    #[serde(default)]
+    pub port: Option<u16>,
+    #[serde(default)]
+    pub url: Option<String>,
+    #[serde(default)]
+    pub server_version: Option<String>,
+    #[serde(default)]
+    pub capabilities: Vec<String>,
+    /// Lets an adopted server be reopened and shut down without logging in again.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub session_id: Option<String>,
+}
+
+/// A server left running by a previous app run, as shown to the UI.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OrphanServer {
+    pub pid: u32,
+    pub port: Option<u16>,
+    pub url: Option<String>,
+    pub started_at: u64,
+    pub server_version: Option<String>,
+    /// Only servers that finished starting can be reconnected to.
+    pub adoptable: bool,
+}
+
+impl From<&ServerLock> for OrphanServer {
+    fn from(lock: &ServerLock) -> Self {
+        Self {
+            pid: lock.server_pid,
+            port: lock.port,
+            url: lock.url.clone(),
+            started_at: lock.started_at,
+            server_version: lock.server_version.clone(),
+            adoptable: lock.url.is_some(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OrphanAction {
+    Adopt,
+    Reap,
+}
+
+fn lock_path(app_pid: u32) -> PathBuf {
+    resolve_pid_dir().join(format!("{LOCK_PREFIX}{app_pid}{LOCK_SUFFIX}"))
+}
+
+pub fn write_lock(lock: &ServerLock) -> io::Result<()> {
+    let content = serde_json::to_vec_pretty(lock).map_err(io::Error::other)?;
+    write_private_file(&lock_path(lock.app_pid), &content)
+}
+
+pub fn remove_lock(app_pid: u32) {
+    let _ = fs::remove_file(lock_path(app_pid));
+}
+
+/// Lock files of servers whose app is gone but which are still running. Files that point
+/// at dead or unrelated processes are cleaned up along the way.
+pub fn find_orphans() -> Vec<ServerLock> {
+    let Ok(entries) = fs::read_dir(resolve_pid_dir()) else {
+        return Vec::new();
+    };
+
+    let mut orphans = Vec::new();
+    for entry in entries.flatten() {
+        let name = entry.file_name().to_string_lossy().to_string();
+        if !name.starts_with(LOCK_PREFIX) || !name.ends_with(LOCK_SUFFIX) {
+            continue;
+        }
+        let path = entry.path();
+        let lock = match fs::read_to_string(&path)
+            .ok()
+            .and_then(|content| serde_json::from_str::<ServerLock>(&content).ok())
+        {
+            Some(lock) => lock,
+            None => {
+                let _ = fs::remove_file(&path);
+                continue;
+            }
+        };
+
+        if lock.app_pid == std::process::id() || is_alive(lock.app_pid) {
+            // Our own server, or one that another running app instance still owns.
+            continue;
+        }
+        if !is_alive(lock.server_pid) || !looks_like_server(&lock) {
+            log::info!(path:% = path.display(), pid = lock.server_pid; "removing stale server lock");
+            let _ = fs::remove_file(&path);
+            continue;
+        }
+        orphans.push(lock);
+    }
+    orphans.sort_by_key(|lock| lock.started_at);
+    orphans
+}
+
+pub fn find_orphan(pid: u32) -> Option<ServerLock> {
+    find_orphans()
+        .into_iter()
+        .find(|lock| lock.server_pid == pid)
+}
+
+#[cfg(unix)]
+fn looks_like_server(lock: &ServerLock) -> bool {
+    std::process::Command::new("ps")
+        .args(["-p", &lock.server_pid.to_string(), "-o", "args="])
+        .output()
+        .map(|output| String::from_utf8_lossy(&output.stdout).contains(&lock.entry))
+        .unwrap_or(false)
+}
+
+#[cfg(windows)]
+fn looks_like_server(_lock: &ServerLock) -> bool {
+    // tasklist only reports image names; the pid check has to do.
+    true
+}
@@ -0,0 +1,166 @@
+use std::process::{Child, Command};
+
+/// Starts the command in a process group of its own, so the server and everything it spawns
+/// (OpenCode instances, background processes) can be signalled as a unit.
+pub fn isolate(command: &mut Command) {
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::CommandExt;
+        command.process_group(0);
+    }
+    #[cfg(windows)]
+    {
+        let _ = command;
+    }
+}
+
+/// The server process plus its descendants: a process group on Unix, a Job Object on Windows.
+#[derive(Debug)]
+pub struct ProcessTree {
+    pid: u32,
+    #[cfg(windows)]
+    job: Option<job::Job>,
+}
+
+impl ProcessTree {
+    /// Must be called right after spawning, before the server has started children of its own.
+    pub fn attach(child: &Child) -> Self {
+        Self {
+            pid: child.id(),
+            #[cfg(windows)]
+            job: job::Job::for_child(child)
+                .map_err(|err| log::warn!(error:% = err; "failed to create job object"))
+                .ok(),
+        }
+    }
+
+    /// Wraps a server this app did not spawn itself, e.g. one left behind by a previous run.
+    /// Its group id is still its pid on Unix; on Windows the tree is killed through `taskkill`.
+    pub fn adopt(pid: u32) -> Self {
+        Self {
+            pid,
+            #[cfg(windows)]
+            job: None,
+        }
+    }
+
+    pub fn pid(&self) -> u32 {
+        self.pid
+    }
+
+    /// Asks every process in the group to exit.
+    #[cfg(unix)]
+    pub fn terminate(&self) {
+        signal_group(self.pid, libc::SIGTERM);
+    }
+
+    /// Kills every process in the tree, including ones that outlived the server itself.
+    pub fn kill(&self) {
+        #[cfg(unix)]
+        signal_group(self.pid, libc::SIGKILL);
+        #[cfg(windows)]
+        match &self.job {
+            Some(job) => job.terminate(),
+            None => {
+                let _ = Command::new("taskkill")
+                    .args(["/PID", &self.pid.to_string(), "/T", "/F"])
+                    .status();
+            }
+        }
+    }
+}
+
+pub fn is_alive(pid: u32) -> bool {
+    #[cfg(unix)]
+    {
+        // EPERM still means the process exists, it just belongs to someone else.
+        let result = unsafe { libc::kill(pid as i32, 0) };
+        result == 0 || std::io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+    }
+    #[cfg(windows)]
+    {
+        Command::new("tasklist")
+            .args(["/FI", &format!("PID eq {pid}"), "/FO", "CSV", "/NH"])
+            .output()
+            .map(|output| String::from_utf8_lossy(&output.stdout).contains(&format!("\"{pid}\"")))
+            .unwrap_or(false)
+    }
+}
+
+#[cfg(unix)]
+fn signal_group(pgid: u32, signal: libc::c_int) {
+    // The leader's pid doubles as the group id; a negative pid addresses the whole group.
+    unsafe {
+        libc::kill(-(pgid as i32), signal);
+    }
+}
+
+#[cfg(windows)]
+mod job {
+    use std::ffi::c_void;
+    use std::io;
+    use std::os::windows::io::AsRawHandle;
+    use std::process::Child;
+    use windows_sys::Win32::Foundation::{CloseHandle, HANDLE};
+    use windows_sys::Win32::System::JobObjects::{
+        AssignProcessToJobObject, CreateJobObjectW, JobObjectExtendedLimitInformation,
+        SetInformationJobObject, TerminateJobObject, JOBOBJECT_EXTENDED_LIMIT_INFORMATION,
+        JOB_OBJECT_LIMIT_KILL_ON_JOB_CLOSE,
+    };
+
+    /// Owned Job Object handle. Closing it kills whatever is still inside, so the server tree
+    /// also goes away if the desktop app dies.
+    pub struct Job(HANDLE);
+
+    // The handle is only used through thread-safe kernel calls.
+    unsafe impl Send for Job {}
+    unsafe impl Sync for Job {}
+
+    impl std::fmt::Debug for Job {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            f.debug_tuple("Job").field(&self.0).finish()
+        }
+    }
+
+    impl Job {
+        pub fn for_child(child: &Child) -> io::Result<Self> {
+            unsafe {
+                let handle = CreateJobObjectW(std::ptr::null(), std::ptr::null());
+                if handle.is_null() {
+                    return Err(io::Error::last_os_error());
+                }
+                let job = Job(handle);
+
+                let mut info: JOBOBJECT_EXTENDED_LIMIT_INFORMATION = std::mem::zeroed();
+                info.BasicLimitInformation.LimitFlags = JOB_OBJECT_LIMIT_KILL_ON_JOB_CLOSE;
+                if SetInformationJobObject(
+                    job.0,
+                    JobObjectExtendedLimitInformation,
+                    &info as *const _ as *const c_void,
+                    std::mem::size_of::<JOBOBJECT_EXTENDED_LIMIT_INFORMATION>() as u32,
+                ) == 0
+                {
+                    return Err(io::Error::last_os_error());
+                }
+                if AssignProcessToJobObject(job.0, child.as_raw_handle()) == 0 {
+                    return Err(io::Error::last_os_error());
+                }
+                Ok(job)
+            }
+        }
+
+        pub fn terminate(&self) {
+            unsafe {
+                TerminateJobObject(self.0, 1);
+            }
+        }
+    }
+
+    impl Drop for Job {
+        fn drop(&mut self) {
+            unsafe {
+                CloseHandle(self.0);
+            }
+        }
+    }
+}
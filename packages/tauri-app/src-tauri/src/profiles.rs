@@ -0,0 +1,271 @@
+use crate::config::{
+    resolve_config_dir, resolve_listening_mode, write_private_file, DesktopConfig, ListeningMode,
+    RemoteConfig,
+};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use tauri::Url;
+
+const PROFILES_FILENAME: &str = "profiles.json";
+const DEFAULT_PROFILE_ID: &str = "local";
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(tag = "method", rename_all = "camelCase")]
+pub enum ProfileAuth {
+    /// Local servers only: a one-time token handed to the server when it is spawned.
+    #[default]
+    Token,
+    Password {
+        username: String,
+        /// Falls back to `AGROFORGE_REMOTE_PASSWORD` when unset.
+        #[serde(default)]
+        password: Option<String>,
+    },
+    /// Sign in through the server's own login page.
+    Manual,
+}
+
+/// A server the app can connect to. Profiles without a URL are spawned locally.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ServerProfile {
+    #[serde(default)]
+    pub id: String,
+    pub name: String,
+    #[serde(default)]
+    pub url: Option<String>,
+    /// Passed to a locally spawned server as `--workspace-root`.
+    #[serde(default)]
+    pub workspace_root: Option<String>,
+    #[serde(default)]
+    pub auth: ProfileAuth,
+    /// Interface a locally spawned server binds to; defaults to `preferences.listeningMode`.
+    #[serde(default)]
+    pub listening_mode: Option<ListeningMode>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ProfileList {
+    pub active: Option<String>,
+    pub profiles: Vec<ServerProfile>,
+}
+
+#[derive(Debug, Clone)]
+pub struct LocalTarget {
+    pub listening_mode: ListeningMode,
+    pub workspace_root: Option<String>,
+}
+
+impl Default for LocalTarget {
+    fn default() -> Self {
+        Self {
+            listening_mode: resolve_listening_mode(),
+            workspace_root: None,
+        }
+    }
+}
+
+/// What `CliProcessManager::start` should do.
+#[derive(Debug, Clone)]
+pub enum ConnectionTarget {
+    Local(LocalTarget),
+    Remote(RemoteConfig),
+}
+
+impl ServerProfile {
+    fn default_local() -> Self {
+        Self {
+            id: DEFAULT_PROFILE_ID.to_string(),
+            name: "Local server".to_string(),
+            url: None,
+            workspace_root: None,
+            auth: ProfileAuth::Token,
+            listening_mode: None,
+        }
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        if self.name.trim().is_empty() {
+            return Err("Profile name is required".to_string());
+        }
+        match &self.url {
+            Some(url) => {
+                let parsed =
+                    Url::parse(url).map_err(|err| format!("Invalid server URL {url}: {err}"))?;
+                if !matches!(parsed.scheme(), "http" | "https") {
+                    return Err(format!("Server URL {url} must use http or https"));
+                }
+                if matches!(self.auth, ProfileAuth::Token) {
+                    return Err("Remote servers cannot use token sign-in".to_string());
+                }
+            }
+            None => {
+                if !matches!(self.auth, ProfileAuth::Token) {
+                    return Err("Local servers sign in with a one-time token".to_string());
+                }
+                if let Some(root) = &self.workspace_root {
+                    if !PathBuf::from(root).is_dir() {
+                        return Err(format!("Workspace root {root} is not a directory"));
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn target(&self) -> ConnectionTarget {
+        match &self.url {
+            Some(url) => {
+                let (username, password) = match &self.auth {
+                    ProfileAuth::Password { username, password } => {
+                        (Some(username.clone()), password.clone())
+                    }
+                    ProfileAuth::Token | ProfileAuth::Manual => (None, None),
+                };
+                ConnectionTarget::Remote(RemoteConfig {
+                    url: url.clone(),
+                    username,
+                    password,
+                })
+            }
+            None => ConnectionTarget::Local(LocalTarget {
+                listening_mode: self.listening_mode.unwrap_or_else(resolve_listening_mode),
+                workspace_root: self.workspace_root.clone(),
+            }),
+        }
+    }
+
+    /// Copy that is safe to hand to the webview.
+    fn redacted(&self) -> Self {
+        let mut profile = self.clone();
+        if let ProfileAuth::Password { password, .. } = &mut profile.auth {
+            *password = None;
+        }
+        profile
+    }
+}
+
+fn profiles_path() -> PathBuf {
+    resolve_config_dir().join(PROFILES_FILENAME)
+}
+
+fn load() -> ProfileList {
+    let path = profiles_path();
+    let mut list = match fs::read_to_string(&path) {
+        Ok(content) => serde_json::from_str::<ProfileList>(&content).unwrap_or_else(|err| {
+            log::warn!(path:% = path.display(), error:% = err; "ignoring invalid profiles file");
+            ProfileList::default()
+        }),
+        Err(_) => ProfileList::default(),
+    };
+    if list.profiles.is_empty() {
+        list.profiles.push(ServerProfile::default_local());
+    }
+    list
+}
+
+fn store(list: &ProfileList) -> io::Result<()> {
+    let content = serde_json::to_vec_pretty(list).map_err(io::Error::other)?;
+    write_private_file(&profiles_path(), &content)
+}
+
+/// Saved profiles with their passwords stripped.
+pub fn list() -> ProfileList {
+    let list = load();
+    ProfileList {
+        active: list.active,
+        profiles: list.profiles.iter().map(ServerProfile::redacted).collect(),
+    }
+}
+
+/// Adds or replaces a profile. A password left empty keeps the one already saved, since
+/// `list` never hands passwords out.
+pub fn save(mut profile: ServerProfile) -> Result<ProfileList, String> {
+    profile.validate()?;
+    if let ProfileAuth::Password { password, .. } = &mut profile.auth {
+        if password.as_deref().is_some_and(str::is_empty) {
+            *password = None;
+        }
+    }
+    let mut list = load();
+    if profile.id.trim().is_empty() {
+        profile.id = unique_id(&profile.name, &list);
+    }
+
+    match list.profiles.iter_mut().find(|p| p.id == profile.id) {
+        Some(existing) => {
+            if let (
+                ProfileAuth::Password { password, .. },
+                ProfileAuth::Password {
+                    password: saved, ..
+                },
+            ) = (&mut profile.auth, &existing.auth)
+            {
+                if password.is_none() {
+                    password.clone_from(saved);
+                }
+            }
+            *existing = profile;
+        }
+        None => list.profiles.push(profile),
+    }
+
+    store(&list).map_err(|err| format!("Failed to save profiles: {err}"))?;
+    Ok(self::list())
+}
+
+pub fn set_active(id: &str) -> Result<ServerProfile, String> {
+    let mut list = load();
+    let profile = list
+        .profiles
+        .iter()
+        .find(|p| p.id == id)
+        .cloned()
+        .ok_or_else(|| format!("Unknown server profile {id}"))?;
+    list.active = Some(profile.id.clone());
+    store(&list).map_err(|err| format!("Failed to save profiles: {err}"))?;
+    Ok(profile)
+}
+
+/// The active profile wins; without one, `desktop.json`'s `remote` section or a plain local
+/// server is used.
+pub fn resolve_target(config: &DesktopConfig) -> ConnectionTarget {
+    let list = load();
+    if let Some(profile) = list
+        .active
+        .as_deref()
+        .and_then(|id| list.profiles.iter().find(|p| p.id == id))
+    {
+        return profile.target();
+    }
+    match config
+        .remote
+        .clone()
+        .filter(|remote| !remote.url.trim().is_empty())
+    {
+        Some(remote) => ConnectionTarget::Remote(remote),
+        None => ConnectionTarget::Local(LocalTarget::default()),
+    }
+}
+
+fn unique_id(name: &str, list: &ProfileList) -> String {
+    let slug: String = name
+        .trim()
+        .to_lowercase()
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '-' })
+        .collect();
+    let base = slug.trim_matches('-');
+    let base = if base.is_empty() { "profile" } else { base };
+
+    let mut id = base.to_string();
+    let mut suffix = 2;
+    while list.profiles.iter().any(|p| p.id == id) {
+        id = format!("{base}-{suffix}");
+        suffix += 1;
+    }
+    id
+}
@@ -0,0 +1,144 @@
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+use std::collections::HashSet;
+use tauri::ipc::CapabilityBuilder;
+use tauri::{AppHandle, Manager, Url};
+use url::Host;
+
+/// Narrower than what the bundled capability grants the local server's origin: a remote page
+/// only gets the app commands in the `remote-ui` set, and no native file dialog, since local
+/// paths mean nothing to a server on another machine.
+const REMOTE_PERMISSIONS: &[&str] = &[
+    "core:default",
+    "opener:allow-default-urls",
+    "core:webview:allow-set-webview-zoom",
+    "remote-ui",
+];
+
+/// Origins of remote servers the main window may load, on top of the loopback ones.
+static ALLOWED_ORIGINS: Lazy<Mutex<HashSet<String>>> = Lazy::new(|| Mutex::new(HashSet::new()));
+/// Origins whose capability has been added. Tauri cannot remove a capability again, so revoking
+/// an origin only drops it from `ALLOWED_ORIGINS`, and allowing it again reuses the capability.
+static GRANTED_ORIGINS: Lazy<Mutex<HashSet<String>>> = Lazy::new(|| Mutex::new(HashSet::new()));
+
+/// Where the main window goes when the page it shows is no longer allowed.
+const LOADING_PAGE: &str = if cfg!(windows) {
+    "http://tauri.localhost/loading.html"
+} else {
+    "tauri://localhost/loading.html"
+};
+
+/// `scheme://host[:port]` of a URL, or `None` for URLs without a host.
+pub fn origin_of(url: &Url) -> Option<String> {
+    match url.origin() {
+        origin @ url::Origin::Tuple(..) => Some(origin.ascii_serialization()),
+        url::Origin::Opaque(_) => None,
+    }
+}
+
+pub fn is_allowed(url: &Url) -> bool {
+    origin_of(url).is_some_and(|origin| ALLOWED_ORIGINS.lock().contains(&origin))
+}
+
+/// Whether a password may be posted to `url`: only over TLS, or to this machine.
+pub fn may_send_credentials(url: &Url) -> bool {
+    if url.scheme() == "https" {
+        return true;
+    }
+    match url.host() {
+        Some(Host::Ipv4(ip)) => ip.is_loopback(),
+        Some(Host::Ipv6(ip)) => ip.is_loopback(),
+        Some(Host::Domain(domain)) => domain.eq_ignore_ascii_case("localhost"),
+        None => false,
+    }
+}
+
+/// Lets the main window navigate to `origin` and call the `remote-ui` commands from pages served
+/// there.
+pub fn allow_origin(app: &AppHandle, origin: &str) -> tauri::Result<()> {
+    let mut granted = GRANTED_ORIGINS.lock();
+    if !granted.contains(origin) {
+        let identifier = origin.replace(|c: char| !c.is_ascii_alphanumeric(), "-");
+        let capability = REMOTE_PERMISSIONS.iter().fold(
+            CapabilityBuilder::new(format!("remote-server-{identifier}"))
+                .remote(format!("{origin}/*"))
+                .local(false)
+                .window("main"),
+            |builder, permission| builder.permission(*permission),
+        );
+        app.add_capability(capability)?;
+        granted.insert(origin.to_string());
+    }
+    ALLOWED_ORIGINS.lock().insert(origin.to_string());
+    Ok(())
+}
+
+/// Undoes `allow_origin`. If the main window is showing a page from `origin`, it is sent back to
+/// the loading page, since the capability itself stays registered.
+pub fn revoke_origin(app: &AppHandle, origin: &str) {
+    if !ALLOWED_ORIGINS.lock().remove(origin) {
+        return;
+    }
+    log::info!(origin; "revoked remote server origin");
+    let Some(window) = app.get_webview_window("main") else {
+        return;
+    };
+    let showing = window
+        .url()
+        .ok()
+        .and_then(|url| origin_of(&url))
+        .is_some_and(|current| current == origin);
+    if showing {
+        if let Ok(url) = Url::parse(LOADING_PAGE) {
+            let _ = window.navigate(url);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn url(text: &str) -> Url {
+        Url::parse(text).unwrap()
+    }
+
+    #[test]
+    fn credentials_only_go_over_tls_or_loopback() {
+        assert!(may_send_credentials(&url("https://example.com")));
+        assert!(may_send_credentials(&url("http://127.0.0.1:9898")));
+        assert!(may_send_credentials(&url("http://[::1]:9898")));
+        assert!(may_send_credentials(&url("http://LOCALHOST:9898")));
+        assert!(!may_send_credentials(&url("http://example.com")));
+        assert!(!may_send_credentials(&url("http://192.168.1.20:9898")));
+        assert!(!may_send_credentials(&url("http://localhost.example.com")));
+    }
+
+    #[test]
+    fn remote_pages_get_only_the_remote_command_set() {
+        assert!(REMOTE_PERMISSIONS.contains(&"remote-ui"));
+        assert!(!REMOTE_PERMISSIONS.contains(&"local-ui"));
+        assert!(!REMOTE_PERMISSIONS
+            .iter()
+            .any(|permission| permission.starts_with("dialog:")));
+
+        let manifest = include_str!("../permissions/app.toml");
+        let remote_set = &manifest[manifest.find("\"remote-ui\"").unwrap()..];
+        for command in ["profiles-save", "cli-resolve-orphan", "diagnostics-export"] {
+            assert!(!remote_set.contains(command), "{command}");
+        }
+    }
+
+    #[test]
+    fn origin_keeps_non_default_port_only() {
+        assert_eq!(
+            origin_of(&url("https://example.com:443/path")).as_deref(),
+            Some("https://example.com")
+        );
+        assert_eq!(
+            origin_of(&url("http://example.com:8080/path")).as_deref(),
+            Some("http://example.com:8080")
+        );
+        assert_eq!(origin_of(&url("data:text/plain,hi")), None);
+    }
+}
@@ -0,0 +1,246 @@
+use crate::config::SupervisorConfig;
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Fraction of the computed backoff that is randomised in either direction.
+const JITTER_RATIO: f64 = 0.2;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RestartDecision {
+    Restart { attempt: u32, delay: Duration },
+    GiveUp { reason: String },
+}
+
+/// Decides whether a server that exited unexpectedly should be brought back,
+/// and how long to wait before doing so.
+#[derive(Debug)]
+pub struct Supervisor {
+    policy: SupervisorConfig,
+    attempts: u32,
+    fast_failures: u32,
+}
+
+impl Supervisor {
+    pub fn new(policy: SupervisorConfig) -> Self {
+        Self {
+            policy,
+            attempts: 0,
+            fast_failures: 0,
+        }
+    }
+
+    /// Replaces the policy and clears all counters. Called on every user-initiated start.
+    pub fn reset(&mut self, policy: SupervisorConfig) {
+        self.policy = policy;
+        self.attempts = 0;
+        self.fast_failures = 0;
+    }
+
+    pub fn on_exit(&mut self, uptime: Duration) -> RestartDecision {
+        if !self.policy.enabled {
+            return RestartDecision::GiveUp {
+                reason: "CLI server exited (auto-restart disabled)".to_string(),
+            };
+        }
+
+        if uptime >= Duration::from_secs(self.policy.reset_after_secs) {
+            self.attempts = 0;
+        }
+
+        if uptime < Duration::from_secs(self.policy.crash_loop_window_secs) {
+            self.fast_failures += 1;
+        } else {
+            self.fast_failures = 0;
+        }
+
+        if self.policy.crash_loop_threshold > 0
+            && self.fast_failures >= self.policy.crash_loop_threshold
+        {
+            return RestartDecision::GiveUp {
+                reason: format!(
+                    "CLI server crashed {} times within {}s of starting; giving up",
+                    self.fast_failures, self.policy.crash_loop_window_secs
+                ),
+            };
+        }
+
+        if self.attempts >= self.policy.max_restarts {
+            return RestartDecision::GiveUp {
+                reason: format!(
+                    "CLI server exited; exceeded {} restart attempts",
+                    self.policy.max_restarts
+                ),
+            };
+        }
+
+        self.attempts += 1;
+        RestartDecision::Restart {
+            attempt: self.attempts,
+            delay: self.backoff(self.attempts),
+        }
+    }
+
+    fn backoff(&self, attempt: u32) -> Duration {
+        let exponent = attempt.saturating_sub(1).min(16);
+        let base = self
+            .policy
+            .initial_backoff_ms
+            .saturating_mul(1u64 << exponent)
+            .min(self.policy.max_backoff_ms);
+        let jitter = (base as f64 * JITTER_RATIO * (random_unit() * 2.0 - 1.0)) as i64;
+        Duration::from_millis((base as i64 + jitter).max(0) as u64)
+    }
+}
+
+/// Uniform value in `[0, 1)`, plenty for jitter. `RandomState` brings keys seeded randomly
+/// per process; the call counter and the wall clock make every call hash something new.
+fn random_unit() -> f64 {
+    static CALLS: AtomicU64 = AtomicU64::new(0);
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u64(CALLS.fetch_add(1, Ordering::Relaxed));
+    hasher.write_u128(
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos(),
+    );
+    (hasher.finish() >> 11) as f64 / (1u64 << 53) as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FAST: Duration = Duration::from_secs(1);
+    const SLOW: Duration = Duration::from_secs(60);
+    const LONG: Duration = Duration::from_secs(600);
+
+    fn policy() -> SupervisorConfig {
+        SupervisorConfig {
+            enabled: true,
+            max_restarts: 10,
+            initial_backoff_ms: 1_000,
+            max_backoff_ms: 10_000,
+            crash_loop_threshold: 0,
+            crash_loop_window_secs: 30,
+            reset_after_secs: 300,
+        }
+    }
+
+    fn restart(decision: RestartDecision) -> (u32, Duration) {
+        match decision {
+            RestartDecision::Restart { attempt, delay } => (attempt, delay),
+            RestartDecision::GiveUp { reason } => panic!("gave up: {reason}"),
+        }
+    }
+
+    fn within_jitter(delay: Duration, base_ms: u64) -> bool {
+        let low = (base_ms as f64 * (1.0 - JITTER_RATIO)).floor() as u128;
+        let high = (base_ms as f64 * (1.0 + JITTER_RATIO)).ceil() as u128;
+        (low..=high).contains(&delay.as_millis())
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_the_cap() {
+        let mut supervisor = Supervisor::new(policy());
+        for (expected_attempt, base_ms) in
+            (1..).zip([1_000, 2_000, 4_000, 8_000, 10_000, 10_000, 10_000])
+        {
+            let (attempt, delay) = restart(supervisor.on_exit(SLOW));
+            assert_eq!(attempt, expected_attempt);
+            assert!(
+                within_jitter(delay, base_ms),
+                "attempt {attempt}: {delay:?} is not {base_ms}ms ± jitter"
+            );
+        }
+    }
+
+    #[test]
+    fn backoff_does_not_overflow_on_late_attempts() {
+        let supervisor = Supervisor::new(policy());
+        assert!(within_jitter(supervisor.backoff(u32::MAX), 10_000));
+    }
+
+    #[test]
+    fn gives_up_after_max_restarts() {
+        let mut supervisor = Supervisor::new(SupervisorConfig {
+            max_restarts: 2,
+            ..policy()
+        });
+        assert_eq!(restart(supervisor.on_exit(SLOW)).0, 1);
+        assert_eq!(restart(supervisor.on_exit(SLOW)).0, 2);
+        assert!(matches!(
+            supervisor.on_exit(SLOW),
+            RestartDecision::GiveUp { reason } if reason.contains("2 restart attempts")
+        ));
+    }
+
+    #[test]
+    fn crash_loop_breaker_trips_on_fast_failures_in_a_row() {
+        let mut supervisor = Supervisor::new(SupervisorConfig {
+            crash_loop_threshold: 3,
+            ..policy()
+        });
+        restart(supervisor.on_exit(FAST));
+        restart(supervisor.on_exit(FAST));
+        // A run that outlives the window breaks the streak.
+        restart(supervisor.on_exit(SLOW));
+        restart(supervisor.on_exit(FAST));
+        restart(supervisor.on_exit(FAST));
+        assert!(matches!(
+            supervisor.on_exit(FAST),
+            RestartDecision::GiveUp { reason } if reason.contains("3 times within 30s")
+        ));
+    }
+
+    #[test]
+    fn long_uptime_resets_the_attempt_count() {
+        let mut supervisor = Supervisor::new(SupervisorConfig {
+            max_restarts: 2,
+            ..policy()
+        });
+        restart(supervisor.on_exit(SLOW));
+        restart(supervisor.on_exit(SLOW));
+        let (attempt, delay) = restart(supervisor.on_exit(LONG));
+        assert_eq!(attempt, 1);
+        assert!(within_jitter(delay, 1_000));
+    }
+
+    #[test]
+    fn reset_clears_counters_and_applies_the_new_policy() {
+        let mut supervisor = Supervisor::new(SupervisorConfig {
+            max_restarts: 1,
+            ..policy()
+        });
+        restart(supervisor.on_exit(SLOW));
+        supervisor.reset(SupervisorConfig {
+            max_restarts: 1,
+            initial_backoff_ms: 50,
+            ..policy()
+        });
+        let (attempt, delay) = restart(supervisor.on_exit(SLOW));
+        assert_eq!(attempt, 1);
+        assert!(within_jitter(delay, 50));
+    }
+
+    #[test]
+    fn disabled_supervisor_never_restarts() {
+        let mut supervisor = Supervisor::new(SupervisorConfig {
+            enabled: false,
+            ..policy()
+        });
+        assert!(matches!(
+            supervisor.on_exit(LONG),
+            RestartDecision::GiveUp { .. }
+        ));
+    }
+
+    #[test]
+    fn random_unit_is_in_range_and_varies() {
+        let samples: Vec<f64> = (0..64).map(|_| random_unit()).collect();
+        assert!(samples.iter().all(|value| (0.0..1.0).contains(value)));
+        assert!(samples.windows(2).any(|pair| pair[0] != pair[1]));
+    }
+}
@@ -0,0 +1,335 @@
+use crate::config::LoggingConfig;
+use crate::host::ServerHost;
+use parking_lot::Mutex;
+use serde_json::Value;
+use std::env;
+use std::fs;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, Once};
+use std::thread;
+use std::time::Duration;
+
+/// Grace periods short enough that a test which falls through to signals still finishes
+/// quickly, and monitors that would only add noise switched off.
+const TEST_CONFIG: &str = r#"{
+  "supervisor": { "maxRestarts": 1, "initialBackoffMs": 50, "maxBackoffMs": 50, "crashLoopThreshold": 0 },
+  "health": { "enabled": false },
+  "metrics": { "enabled": false },
+  "shutdown": { "apiGraceMs": 3000, "termGraceMs": 1000 },
+  "startup": { "timeoutSecs": 2, "progressIntervalMs": 100 }
+}"#;
+
+static SETUP: Once = Once::new();
+static NEXT_DIR: AtomicU32 = AtomicU32::new(0);
+
+fn test_root() -> PathBuf {
+    env::temp_dir().join(format!("agroforge-tests-{}", std::process::id()))
+}
+
+/// Points the config and pid directories at a scratch directory and logs to a file there.
+/// Every test that reads config, writes lock files or checks log output calls this first.
+pub fn setup() {
+    SETUP.call_once(|| {
+        let root = test_root();
+        fs::create_dir_all(root.join("config")).expect("create test config dir");
+        fs::write(root.join("config/desktop.json"), TEST_CONFIG).expect("write desktop.json");
+        env::set_var("CLI_CONFIG", root.join("config/config.json"));
+        // Server lock files live under the home directory; keep them out of the real one.
+        env::set_var("HOME", &root);
+        crate::logs::init(false);
+        log::set_max_level(log::LevelFilter::Trace);
+        crate::logs::init_log_file(&root.join("logs"), &LoggingConfig::default())
+            .expect("open test log file");
+    });
+}
+
+/// A fresh, empty directory for one test.
+pub fn scratch_dir(name: &str) -> PathBuf {
+    setup();
+    let index = NEXT_DIR.fetch_add(1, Ordering::SeqCst);
+    let dir = test_root().join(format!("{name}-{index}"));
+    fs::create_dir_all(&dir).expect("create scratch dir");
+    dir
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum HostCall {
+    Emit {
+        event: String,
+        payload: Value,
+    },
+    Navigate(String),
+    SetCookie {
+        base_url: String,
+        name: String,
+        value: String,
+    },
+    AllowOrigin(String),
+    RevokeOrigin(String),
+}
+
+/// `ServerHost` that records every call instead of driving a window.
+#[derive(Debug, Default)]
+pub struct MockHost {
+    calls: Mutex<Vec<HostCall>>,
+}
+
+impl MockHost {
+    pub fn new() -> Arc<Self> {
+        Arc::new(Self::default())
+    }
+
+    pub fn calls(&self) -> Vec<HostCall> {
+        self.calls.lock().clone()
+    }
+
+    /// Payloads of every `event` emitted so far, oldest first.
+    pub fn emitted(&self, event: &str) -> Vec<Value> {
+        self.calls()
+            .into_iter()
+            .filter_map(|call| match call {
+                HostCall::Emit {
+                    event: name,
+                    payload,
+                } if name == event => Some(payload),
+                _ => None,
+            })
+            .collect()
+    }
+
+    pub fn navigations(&self) -> Vec<String> {
+        self.calls()
+            .into_iter()
+            .filter_map(|call| match call {
+                HostCall::Navigate(url) => Some(url),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// `(name, value)` of every cookie set so far.
+    pub fn cookies(&self) -> Vec<(String, String)> {
+        self.calls()
+            .into_iter()
+            .filter_map(|call| match call {
+                HostCall::SetCookie { name, value, .. } => Some((name, value)),
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+impl ServerHost for MockHost {
+    fn emit_value(&self, event: &str, payload: Value) {
+        self.calls.lock().push(HostCall::Emit {
+            event: event.to_string(),
+            payload,
+        });
+    }
+
+    fn navigate_main(&self, url: &str) {
+        self.calls.lock().push(HostCall::Navigate(url.to_string()));
+    }
+
+    fn set_cookie(&self, base_url: &str, name: &str, value: &str) -> anyhow::Result<()> {
+        self.calls.lock().push(HostCall::SetCookie {
+            base_url: base_url.to_string(),
+            name: name.to_string(),
+            value: value.to_string(),
+        });
+        Ok(())
+    }
+
+    fn allow_origin(&self, origin: &str) -> anyhow::Result<()> {
+        self.calls
+            .lock()
+            .push(HostCall::AllowOrigin(origin.to_string()));
+        Ok(())
+    }
+
+    fn revoke_origin(&self, origin: &str) {
+        self.calls
+            .lock()
+            .push(HostCall::RevokeOrigin(origin.to_string()));
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct FakeRequest {
+    pub method: String,
+    pub path: String,
+    pub body: String,
+}
+
+/// Stand-in for the server: an HTTP endpoint on loopback, plus a shell script to run in place
+/// of the server entry. The script starts with `PORT` and `DIR` set and is built from the
+/// snippets below.
+pub struct FakeServer {
+    dir: PathBuf,
+    port: u16,
+    requests: Arc<Mutex<Vec<FakeRequest>>>,
+}
+
+impl FakeServer {
+    /// Appends the script's pid to `pids`, so a test can check on every process it started.
+    pub const RECORD_PID: &'static str = r#"echo $$ >> "$DIR/pids""#;
+    /// Hands the bootstrap token to the HTTP side, which accepts no other.
+    pub const SHARE_TOKEN: &'static str =
+        r#"printf '%s' "$AGROFORGE_BOOTSTRAP_TOKEN" > "$DIR/token""#;
+    /// The readiness handshake, advertising the shutdown API.
+    pub const HANDSHAKE: &'static str = r#"echo "{\"handshake\":\"agroforge\",\"protocolVersion\":1,\"host\":\"127.0.0.1\",\"port\":$PORT,\"pid\":$$,\"serverVersion\":\"0.0.0-test\",\"capabilities\":[\"shutdown-api\"]}""#;
+    /// Makes the HTTP side take two seconds to answer sign-in requests.
+    pub const SLOW_AUTH: &'static str = r#"touch "$DIR/slow-auth""#;
+    /// Leaves a child and a grandchild behind, both ignoring `SIGTERM` like the server does.
+    pub const FORK_CHILDREN: &'static str =
+        r#"trap '' TERM; sleep 600 & sh -c 'sleep 600 & wait' &"#;
+    /// Idles until `POST /api/server/shutdown` arrives, then exits cleanly. The request is
+    /// consumed, so a server started afterwards keeps running.
+    pub const SERVE: &'static str =
+        r#"while [ ! -e "$DIR/shutdown" ]; do sleep 0.05; done; rm -f "$DIR/shutdown""#;
+    /// Session handed out for a valid token or password.
+    pub const SESSION: &'static str = "fake-session";
+    pub const PASSWORD: &'static str = "fake-password";
+
+    pub fn start(script: &[&str]) -> Self {
+        let dir = scratch_dir("fake-server");
+        let listener = TcpListener::bind("127.0.0.1:0").expect("bind fake server");
+        let port = listener.local_addr().expect("fake server address").port();
+        fs::write(
+            dir.join("server.sh"),
+            format!(
+                "PORT={port}\nDIR='{}'\n{}\n",
+                dir.display(),
+                script.join("\n")
+            ),
+        )
+        .expect("write fake server script");
+
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let (root, recorded) = (dir.clone(), requests.clone());
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let (root, recorded) = (root.clone(), recorded.clone());
+                thread::spawn(move || {
+                    let _ = respond(stream, &root, &recorded);
+                });
+            }
+        });
+
+        Self {
+            dir,
+            port,
+            requests,
+        }
+    }
+
+    pub fn script(&self) -> String {
+        self.dir.join("server.sh").display().to_string()
+    }
+
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    pub fn base_url(&self) -> String {
+        format!("http://127.0.0.1:{}", self.port)
+    }
+
+    /// Pids written by `RECORD_PID`, in start order.
+    pub fn pids(&self) -> Vec<u32> {
+        fs::read_to_string(self.dir.join("pids"))
+            .unwrap_or_default()
+            .lines()
+            .filter_map(|line| line.trim().parse().ok())
+            .collect()
+    }
+
+    /// Requests received for `path`, oldest first.
+    pub fn requests(&self, path: &str) -> Vec<FakeRequest> {
+        self.requests
+            .lock()
+            .iter()
+            .filter(|request| request.path == path)
+            .cloned()
+            .collect()
+    }
+}
+
+fn respond(stream: TcpStream, dir: &Path, recorded: &Mutex<Vec<FakeRequest>>) -> io::Result<()> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    let mut parts = request_line.split_whitespace();
+    let method = parts.next().unwrap_or_default().to_string();
+    let path = parts.next().unwrap_or_default().to_string();
+
+    let mut length = 0;
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 || line.trim_end().is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            if name.eq_ignore_ascii_case("content-length") {
+                length = value.trim().parse().unwrap_or(0);
+            }
+        }
+    }
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body)?;
+    let body = String::from_utf8_lossy(&body).into_owned();
+    let json = serde_json::from_str::<Value>(&body).unwrap_or(Value::Null);
+    recorded.lock().push(FakeRequest {
+        method: method.clone(),
+        path: path.clone(),
+        body,
+    });
+
+    if path.starts_with("/api/auth/") && dir.join("slow-auth").exists() {
+        thread::sleep(Duration::from_secs(2));
+    }
+    let authorized = match (method.as_str(), path.as_str()) {
+        ("POST", "/api/auth/token") => fs::read_to_string(dir.join("token"))
+            .is_ok_and(|token| json["token"].as_str() == Some(token.as_str())),
+        ("POST", "/api/auth/login") => json["password"].as_str() == Some(FakeServer::PASSWORD),
+        ("POST", "/api/server/shutdown") => {
+            fs::write(dir.join("shutdown"), "")?;
+            true
+        }
+        _ => true,
+    };
+    let response = if !authorized {
+        "HTTP/1.1 401 Unauthorized\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_string()
+    } else if path.starts_with("/api/auth/") {
+        format!(
+            "HTTP/1.1 200 OK\r\nSet-Cookie: codenomad_session={}; Path=/; HttpOnly\r\nContent-Length: 2\r\nConnection: close\r\n\r\n{{}}",
+            FakeServer::SESSION
+        )
+    } else {
+        "HTTP/1.1 200 OK\r\nContent-Length: 2\r\nConnection: close\r\n\r\n{}".to_string()
+    };
+    (&stream).write_all(response.as_bytes())
+}
+
+/// Live (non-zombie) members of process group `pgid`, read from `/proc`.
+#[cfg(target_os = "linux")]
+pub fn group_members(pgid: u32) -> Vec<u32> {
+    let Ok(entries) = fs::read_dir("/proc") else {
+        return Vec::new();
+    };
+    entries
+        .flatten()
+        .filter_map(|entry| {
+            let pid = entry.file_name().to_str()?.parse::<u32>().ok()?;
+            let stat = fs::read_to_string(entry.path().join("stat")).ok()?;
+            // The command name may contain spaces; the fields after it are state, ppid, pgrp.
+            let mut fields = stat.rsplit_once(')')?.1.split_whitespace();
+            let state = fields.next()?;
+            let group = fields.nth(1)?.parse::<u32>().ok()?;
+            (group == pgid && state != "Z").then_some(pid)
+        })
+        .collect()
+}
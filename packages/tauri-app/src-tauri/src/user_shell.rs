@@ -0,0 +1,438 @@
+use once_cell::sync::OnceCell;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::io::Read;
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Long enough for slow rc files (nvm's init alone can take a second), short enough that a
+/// shell stuck on a prompt does not hold up the first launch for long.
+const CAPTURE_TIMEOUT: Duration = Duration::from_secs(5);
+/// Printed around the `env` dump so whatever the rc files echo can be told apart from it.
+const MARKER: &str = "__AGROFORGE_LOGIN_ENV__";
+/// Variables that describe the capture shell itself rather than the user's setup, plus
+/// npm's prefix, which breaks nvm when inherited.
+const IGNORED_VARS: &[&str] = &[
+    "_",
+    "PWD",
+    "OLDPWD",
+    "SHLVL",
+    "npm_config_prefix",
+    "NPM_CONFIG_PREFIX",
+];
+
+static LOGIN_ENV: OnceCell<Result<HashMap<String, String>, String>> = OnceCell::new();
+static USER_SHELL: OnceCell<UserShell> = OnceCell::new();
+
+/// Environment of the user's login shell, captured on first use and cached for the rest of
+/// the run. `None` on Windows, or when the capture failed; that is logged once.
+pub fn login_env() -> Option<&'static HashMap<String, String>> {
+    LOGIN_ENV.get_or_init(capture_login_env).as_ref().ok()
+}
+
+/// How the capture went, without starting one: `None` until `login_env` has been called.
+pub fn login_env_outcome() -> Option<Result<&'static HashMap<String, String>, &'static str>> {
+    LOGIN_ENV
+        .get()
+        .map(|outcome| outcome.as_ref().map_err(String::as_str))
+}
+
+fn capture_login_env() -> Result<HashMap<String, String>, String> {
+    if !cfg!(unix) {
+        return Err("not captured on this platform".to_string());
+    }
+    let shell = UserShell::detect();
+    let started = Instant::now();
+    match capture(shell) {
+        Ok(env) => {
+            log::info!(
+                shell = shell.path.as_str(),
+                vars = env.len(),
+                elapsed_ms = started.elapsed().as_millis() as u64;
+                "captured login shell environment"
+            );
+            Ok(env)
+        }
+        Err(err) => {
+            log::warn!(shell = shell.path.as_str(), error:% = err; "failed to capture login shell environment");
+            Err(err.to_string())
+        }
+    }
+}
+
+fn capture(shell: &UserShell) -> anyhow::Result<HashMap<String, String>> {
+    let script = shell.kind.capture_script();
+    let mut child = Command::new(&shell.path)
+        .args(shell.args(&script))
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()?;
+
+    // Drain stdout on its own thread so a chatty rc file cannot fill the pipe while we wait.
+    let mut stdout = child.stdout.take().expect("stdout is piped");
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        let mut output = Vec::new();
+        let _ = stdout.read_to_end(&mut output);
+        let _ = sender.send(output);
+    });
+
+    let deadline = Instant::now() + CAPTURE_TIMEOUT;
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+        if Instant::now() >= deadline {
+            let _ = child.kill();
+            let _ = child.wait();
+            anyhow::bail!("timed out after {}s", CAPTURE_TIMEOUT.as_secs());
+        }
+        thread::sleep(Duration::from_millis(20));
+    };
+    if !status.success() {
+        anyhow::bail!("shell exited with {status}");
+    }
+    // Something the rc files started in the background may still hold the pipe open.
+    let output = receiver
+        .recv_timeout(deadline.saturating_duration_since(Instant::now()))
+        .map_err(|_| anyhow::anyhow!("shell output did not end"))?;
+
+    let output = String::from_utf8_lossy(&output);
+    let dump = output
+        .split(MARKER)
+        .nth(1)
+        .ok_or_else(|| anyhow::anyhow!("environment markers missing from shell output"))?;
+    let env = parse_env(dump);
+    if !env.contains_key("PATH") {
+        anyhow::bail!("captured environment has no PATH");
+    }
+    Ok(env)
+}
+
+/// Parses `env -0` output, or plain `env` output as a fallback. In the latter a line that does
+/// not start a new `NAME=` belongs to the previous value, which had a newline in it.
+fn parse_env(dump: &str) -> HashMap<String, String> {
+    let dump = dump.trim_matches('\n');
+    if dump.contains('\0') {
+        return dump
+            .split('\0')
+            .filter_map(|entry| entry.split_once('='))
+            .filter(|(name, _)| is_var_name(name) && !IGNORED_VARS.contains(name))
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect();
+    }
+
+    let mut env: Vec<(String, String)> = Vec::new();
+    for line in dump.lines() {
+        match line.split_once('=') {
+            Some((name, value)) if is_var_name(name) => {
+                env.push((name.to_string(), value.to_string()));
+            }
+            _ => {
+                if let Some((_, value)) = env.last_mut() {
+                    value.push('\n');
+                    value.push_str(line);
+                }
+            }
+        }
+    }
+    env.into_iter()
+        .filter(|(name, _)| !IGNORED_VARS.contains(&name.as_str()))
+        .collect()
+}
+
+fn is_var_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .is_some_and(|c| c == '_' || c.is_ascii_alphabetic())
+        && chars.all(|c| c == '_' || c.is_ascii_alphanumeric())
+}
+
+/// Used when `$SHELL` is something no builder below knows how to drive.
+const FALLBACK_SHELL: &str = "/bin/sh";
+
+/// Shell syntaxes `-c` commands can be written in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ShellKind {
+    /// sh, bash, dash, ksh and friends.
+    Posix,
+    /// POSIX syntax, but PATH setup usually lives in `.zshrc`, which needs `-i`.
+    Zsh,
+    Fish,
+    Nu,
+    /// PowerShell 7 (`pwsh`), which some people use as their login shell on macOS and Linux.
+    Pwsh,
+}
+
+impl ShellKind {
+    /// Recognizes a shell by its basename; `None` for shells without a builder.
+    pub fn detect(shell: &str) -> Option<Self> {
+        let name = Path::new(shell)
+            .file_name()
+            .and_then(OsStr::to_str)
+            .unwrap_or("")
+            .to_lowercase();
+        match name.trim_end_matches(".exe") {
+            "sh" | "bash" | "dash" | "ash" | "ksh" | "mksh" | "yash" => Some(ShellKind::Posix),
+            "zsh" => Some(ShellKind::Zsh),
+            "fish" => Some(ShellKind::Fish),
+            "nu" | "nushell" => Some(ShellKind::Nu),
+            "pwsh" | "powershell" => Some(ShellKind::Pwsh),
+            _ => None,
+        }
+    }
+
+    /// Arguments that start a login shell running `command`.
+    fn args(self, command: &str) -> Vec<String> {
+        let flags: &[&str] = match self {
+            ShellKind::Posix | ShellKind::Fish | ShellKind::Nu => &["-l", "-c"],
+            ShellKind::Zsh => &["-l", "-i", "-c"],
+            // `-Login` is only honoured as the very first argument.
+            ShellKind::Pwsh => &["-Login", "-NoLogo", "-NonInteractive", "-Command"],
+        };
+        flags
+            .iter()
+            .map(|flag| flag.to_string())
+            .chain(std::iter::once(command.to_string()))
+            .collect()
+    }
+
+    /// Quotes `word` so the shell passes it through as a single, literal argument.
+    pub fn quote(self, word: &str) -> String {
+        // PowerShell reads a bare `a,b` as an array and `@x` as splatting, so it always quotes.
+        if self != ShellKind::Pwsh && !word.is_empty() && word.chars().all(is_safe) {
+            return word.to_string();
+        }
+        match self {
+            // Nothing is special inside single quotes except the quote itself.
+            ShellKind::Posix | ShellKind::Zsh => format!("'{}'", word.replace('\'', "'\\''")),
+            // fish also treats backslash as an escape inside single quotes.
+            ShellKind::Fish => format!("'{}'", word.replace('\\', "\\\\").replace('\'', "\\'")),
+            // nu's single-quoted strings cannot hold a quote, so use escaped double quotes,
+            // which are not interpolated without a leading `$`.
+            ShellKind::Nu => {
+                let mut quoted = String::with_capacity(word.len() + 2);
+                quoted.push('"');
+                for c in word.chars() {
+                    match c {
+                        '"' => quoted.push_str("\\\""),
+                        '\\' => quoted.push_str("\\\\"),
+                        '\n' => quoted.push_str("\\n"),
+                        '\r' => quoted.push_str("\\r"),
+                        '\t' => quoted.push_str("\\t"),
+                        c if c.is_control() => quoted.push_str(&format!("\\u{{{:x}}}", c as u32)),
+                        c => quoted.push(c),
+                    }
+                }
+                quoted.push('"');
+                quoted
+            }
+            // PowerShell doubles quotes to escape them, and also treats the typographic
+            // single quotes as quote characters.
+            ShellKind::Pwsh => {
+                let mut quoted = String::with_capacity(word.len() + 2);
+                quoted.push('\'');
+                for c in word.chars() {
+                    if matches!(c, '\'' | '\u{2018}' | '\u{2019}' | '\u{201a}' | '\u{201b}') {
+                        quoted.push(c);
+                    }
+                    quoted.push(c);
+                }
+                quoted.push('\'');
+                quoted
+            }
+        }
+    }
+
+    /// A command that runs `words` with `ELECTRON_RUN_AS_NODE=1`, replacing the shell where
+    /// the shell can.
+    pub fn exec_command(self, words: &[String]) -> String {
+        let quoted: Vec<String> = words.iter().map(|word| self.quote(word)).collect();
+        let quoted = quoted.join(" ");
+        match self {
+            ShellKind::Posix | ShellKind::Zsh => format!("ELECTRON_RUN_AS_NODE=1 exec {quoted}"),
+            ShellKind::Fish => format!("set -gx ELECTRON_RUN_AS_NODE 1; exec {quoted}"),
+            ShellKind::Nu => format!("$env.ELECTRON_RUN_AS_NODE = '1'; exec {quoted}"),
+            // No `exec`; the server stays a child of pwsh, in the same process group.
+            ShellKind::Pwsh => {
+                format!("$env:ELECTRON_RUN_AS_NODE = '1'; & {quoted}; exit $LASTEXITCODE")
+            }
+        }
+    }
+
+    /// Prints the environment between two `MARKER` lines.
+    fn capture_script(self) -> String {
+        match self {
+            // `env -0` keeps values with newlines intact; not every `env` has it.
+            ShellKind::Posix | ShellKind::Zsh | ShellKind::Fish => format!(
+                "printf '%s\\n' {MARKER}; command env -0 2>/dev/null || command env; printf '%s\\n' {MARKER}"
+            ),
+            ShellKind::Nu => format!("print {MARKER}; ^env; print {MARKER}"),
+            ShellKind::Pwsh => format!(
+                "'{MARKER}'; Get-ChildItem env: | ForEach-Object {{ $_.Name + '=' + $_.Value }}; '{MARKER}'"
+            ),
+        }
+    }
+}
+
+/// Characters the POSIX-like shells, fish and nu give no meaning to. `=` is left out because a
+/// leading `NAME=` word is an assignment, and `~` because a leading one is expanded.
+fn is_safe(c: char) -> bool {
+    c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.' | '/' | ',' | ':' | '+' | '@')
+}
+
+/// The user's login shell and the syntax to speak to it.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UserShell {
+    pub path: String,
+    pub kind: ShellKind,
+    /// The shell `$SHELL` named when it had no builder; `path` is then the fallback.
+    pub unsupported: Option<String>,
+}
+
+impl UserShell {
+    /// `$SHELL`, or the platform's default shell when it is unset. A shell without a builder
+    /// is swapped for `/bin/sh`, which still reads the POSIX login profile. Worked out on first
+    /// use, so an unsupported shell is only reported once.
+    pub fn detect() -> &'static Self {
+        USER_SHELL.get_or_init(Self::from_env)
+    }
+
+    fn from_env() -> Self {
+        let path = default_shell();
+        match ShellKind::detect(&path) {
+            Some(kind) => Self {
+                path,
+                kind,
+                unsupported: None,
+            },
+            None => {
+                log::warn!(shell = path.as_str(), fallback = FALLBACK_SHELL; "unsupported login shell");
+                Self {
+                    path: FALLBACK_SHELL.to_string(),
+                    kind: ShellKind::Posix,
+                    unsupported: Some(path),
+                }
+            }
+        }
+    }
+
+    /// Arguments that make this shell run `command` as a login shell.
+    pub fn args(&self, command: &str) -> Vec<String> {
+        self.kind.args(command)
+    }
+}
+
+fn default_shell() -> String {
+    if let Ok(shell) = std::env::var("SHELL") {
+        if !shell.trim().is_empty() {
+            return shell;
+        }
+    }
+    if cfg!(target_os = "macos") {
+        "/bin/zsh".to_string()
+    } else {
+        "/bin/bash".to_string()
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+    use std::path::PathBuf;
+
+    #[test]
+    fn shell_is_detected_once() {
+        assert!(std::ptr::eq(UserShell::detect(), UserShell::detect()));
+    }
+
+    #[test]
+    fn quotes_only_what_needs_it() {
+        assert_eq!(
+            ShellKind::Posix.quote("/opt/app/server.js"),
+            "/opt/app/server.js"
+        );
+        assert_eq!(ShellKind::Posix.quote(""), "''");
+        assert_eq!(ShellKind::Posix.quote("~/x"), "'~/x'");
+        assert_eq!(ShellKind::Posix.quote("it's"), "'it'\\''s'");
+        assert_eq!(ShellKind::Fish.quote("a\\b'c"), "'a\\\\b\\'c'");
+        assert_eq!(ShellKind::Nu.quote("a \"b\"\n"), "\"a \\\"b\\\"\\n\"");
+        assert_eq!(ShellKind::Pwsh.quote("it's"), "'it''s'");
+    }
+
+    #[test]
+    fn pwsh_quotes_even_plain_words() {
+        assert_eq!(ShellKind::Pwsh.quote("a,b"), "'a,b'");
+        assert_eq!(ShellKind::Pwsh.quote("@args"), "'@args'");
+        assert_eq!(
+            ShellKind::Pwsh.quote("/opt/app/server.js"),
+            "'/opt/app/server.js'"
+        );
+        assert_eq!(ShellKind::Posix.quote("a,b"), "a,b");
+    }
+
+    /// Shells installed here, each with a command that prints one argument verbatim.
+    fn installed_shells() -> Vec<(PathBuf, ShellKind)> {
+        ["sh", "bash", "dash", "zsh", "fish", "nu", "pwsh"]
+            .into_iter()
+            .filter_map(|name| {
+                let path = which::which(name).ok()?;
+                Some((path, ShellKind::detect(name)?))
+            })
+            .collect()
+    }
+
+    fn printed_by(shell: &Path, kind: ShellKind, word: &str) -> String {
+        let quoted = kind.quote(word);
+        let (flags, command): (&[&str], String) = match kind {
+            ShellKind::Posix | ShellKind::Fish => (&["-c"], format!("printf '%s' {quoted}")),
+            // No rc files, which could print or redefine printf.
+            ShellKind::Zsh => (&["-f", "-c"], format!("printf '%s' {quoted}")),
+            ShellKind::Nu => (&["-c"], format!("print -n {quoted}")),
+            ShellKind::Pwsh => (
+                &["-NoProfile", "-NonInteractive", "-Command"],
+                format!("[Console]::Out.Write({quoted})"),
+            ),
+        };
+        let output = Command::new(shell)
+            .args(flags)
+            .arg(command)
+            .output()
+            .expect("run shell");
+        String::from_utf8_lossy(&output.stdout).into_owned()
+    }
+
+    /// Words heavy on characters that mean something to at least one shell.
+    fn hostile_word() -> impl Strategy<Value = String> {
+        prop_oneof![
+            "[~]?[a-z;&|*?()\\[\\]{}<>$`'\"\\\\ \t\n#!=%^,@:+.~-]{0,24}",
+            "[^\\x00]{0,16}",
+        ]
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(64))]
+
+        #[test]
+        fn quoted_words_reach_the_command_verbatim(word in hostile_word()) {
+            for (shell, kind) in installed_shells() {
+                prop_assert_eq!(
+                    printed_by(&shell, kind, &word),
+                    word.clone(),
+                    "{} with {:?}",
+                    shell.display(),
+                    kind.quote(&word)
+                );
+            }
+        }
+    }
+}
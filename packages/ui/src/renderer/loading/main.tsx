@@ -1,4 +1,4 @@
-import { Show, createSignal, onCleanup, onMount } from "solid-js"
+import { For, Show, createSignal, onCleanup, onMount } from "solid-js"
 import { render } from "solid-js/web"
 import iconUrl from "../../images/AgroForge-Icon.png"
 import { runtimeEnv, isTauriHost } from "../../lib/runtime-env"
@@ -24,6 +24,33 @@ interface CliStatus {
   error?: string | null
 }
 
+interface OrphanServer {
+  pid: number
+  port?: number | null
+  url?: string | null
+  startedAt: number
+  serverVersion?: string | null
+  adoptable: boolean
+}
+
+type OrphanAction = "adopt" | "reap"
+
+interface LogEntry {
+  seq: number
+  timestamp: number
+  stream: "stdout" | "stderr"
+  level: "trace" | "debug" | "info" | "warn" | "error"
+  message: string
+}
+
+const MAX_VISIBLE_LOGS = 50
+
+interface StartupProgress {
+  pid: number
+  elapsedMs: number
+  timeoutMs?: number | null
+}
+
 interface TauriBridge {
   invoke?: <T = unknown>(cmd: string, args?: Record<string, unknown>) => Promise<T>
   event?: {
@@ -66,6 +93,10 @@ function LoadingApp() {
   const [phrase, setPhrase] = createSignal(pickPhrase())
   const [error, setError] = createSignal<string | null>(null)
   const [status, setStatus] = createSignal<string | null>(null)
+  const [orphans, setOrphans] = createSignal<OrphanServer[]>([])
+  const [resolvingPid, setResolvingPid] = createSignal<number | null>(null)
+  const [logs, setLogs] = createSignal<LogEntry[]>([])
+  let resolveOrphan: ((pid: number, action: OrphanAction) => Promise<void>) | null = null
 
   const changePhrase = () => setPhrase(pickPhrase(phrase()))
 
@@ -104,7 +135,62 @@ function LoadingApp() {
             setStatus(null)
           }
         })
-        unsubscribers.push(readyUnlisten, errorUnlisten, statusUnlisten)
+        const orphansUnlisten = await tauriBridge.event.listen("cli:orphans", (event) => {
+          const payload = (event?.payload as OrphanServer[]) || []
+          setOrphans(payload)
+          if (payload.length > 0) {
+            setStatus("Found a server from a previous session")
+          }
+        })
+        const progressUnlisten = await tauriBridge.event.listen("cli:startup-progress", (event) => {
+          const payload = event?.payload as StartupProgress | undefined
+          if (!payload || error() || orphans().length > 0) return
+          const elapsed = Math.floor(payload.elapsedMs / 1000)
+          const limit = payload.timeoutMs ? ` of ${Math.floor(payload.timeoutMs / 1000)}s` : ""
+          setStatus(`Starting server… ${elapsed}s${limit}`)
+        })
+        const logUnlisten = await tauriBridge.event.listen("cli:log", (event) => {
+          const entry = event?.payload as LogEntry | undefined
+          if (!entry) return
+          setLogs((previous) => [...previous, entry].slice(-MAX_VISIBLE_LOGS))
+        })
+        unsubscribers.push(
+          readyUnlisten,
+          errorUnlisten,
+          statusUnlisten,
+          orphansUnlisten,
+          progressUnlisten,
+          logUnlisten,
+        )
+
+        const recent = (await tauriBridge.invoke<LogEntry[]>("cli_get_logs", { limit: MAX_VISIBLE_LOGS })) ?? []
+        setLogs((previous) => {
+          const newest = recent.length > 0 ? recent[recent.length - 1].seq : 0
+          return [...recent, ...previous.filter((entry) => entry.seq > newest)].slice(-MAX_VISIBLE_LOGS)
+        })
+
+        resolveOrphan = async (pid, action) => {
+          setResolvingPid(pid)
+          setError(null)
+          try {
+            await tauriBridge.invoke?.("cli_resolve_orphan", { pid, action })
+            const remaining = (await tauriBridge.invoke?.<OrphanServer[]>("cli_get_orphans")) ?? []
+            setOrphans(action === "adopt" ? [] : remaining)
+            if (action === "reap" && remaining.length === 0) {
+              setStatus(null)
+            }
+          } catch (err) {
+            setError(String(err))
+          } finally {
+            setResolvingPid(null)
+          }
+        }
+
+        const pending = await tauriBridge.invoke<OrphanServer[]>("cli_get_orphans")
+        if (pending && pending.length > 0) {
+          setOrphans(pending)
+          setStatus("Found a server from a previous session")
+        }
 
         const result = await tauriBridge.invoke<CliStatus>("cli_get_status")
         if (result?.state === "ready" && result.url) {
@@ -151,7 +237,52 @@ function LoadingApp() {
             Show another
           </button>
         </div>
+        <Show when={orphans().length > 0}>
+          <div class="orphan-list">
+            <p class="orphan-intro">
+              AgroForge did not shut down cleanly last time and its server is still running.
+            </p>
+            <For each={orphans()}>
+              {(orphan) => (
+                <div class="orphan-row">
+                  <div class="orphan-details">
+                    <span>
+                      {orphan.port ? `Port ${orphan.port}` : "Not ready"} · pid {orphan.pid}
+                    </span>
+                    <span class="orphan-meta">Started {new Date(orphan.startedAt).toLocaleString()}</span>
+                  </div>
+                  <div class="orphan-actions">
+                    <button
+                      type="button"
+                      disabled={!orphan.adoptable || resolvingPid() !== null}
+                      onClick={() => void resolveOrphan?.(orphan.pid, "adopt")}
+                    >
+                      Reconnect
+                    </button>
+                    <button
+                      type="button"
+                      disabled={resolvingPid() !== null}
+                      onClick={() => void resolveOrphan?.(orphan.pid, "reap")}
+                    >
+                      {resolvingPid() === orphan.pid ? "Stopping…" : "Stop it"}
+                    </button>
+                  </div>
+                </div>
+              )}
+            </For>
+          </div>
+        </Show>
         {error() && <div class="loading-error">{error()}</div>}
+        <Show when={error() && logs().length > 0}>
+          <details class="loading-logs">
+            <summary>Server output</summary>
+            <pre>
+              <For each={logs()}>
+                {(entry) => <div class={`loading-log-line loading-log-${entry.level}`}>{entry.message}</div>}
+              </For>
+            </pre>
+          </details>
+        </Show>
       </div>
     </div>
   )